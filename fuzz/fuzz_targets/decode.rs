@@ -0,0 +1,63 @@
+#![no_main]
+
+use chip8::instruction::Instruction;
+use chip8::opcode::OpCode;
+use libfuzzer_sys::fuzz_target;
+
+// Re-encodes a decoded instruction, so every operand can be checked against the original word.
+fn encode(opcode: &OpCode) -> Option<u16> {
+    let xy = |vx: u16, vy: u16| vx << 8 | vy << 4;
+
+    let word = match *opcode {
+        OpCode::NOOP => return None,
+        OpCode::CLS => 0x00E0,
+        OpCode::RET => 0x00EE,
+        OpCode::JP { addr } => 0x1000 | addr,
+        OpCode::CALL { addr } => 0x2000 | addr,
+        OpCode::SE { vx, other, by_value: true } => 0x3000 | vx << 8 | other,
+        OpCode::SNE { vx, other, by_value: true } => 0x4000 | vx << 8 | other,
+        OpCode::SE { vx, other, by_value: false } => 0x5000 | xy(vx, other),
+        OpCode::LD { vx, other, by_value: true } => 0x6000 | vx << 8 | other,
+        OpCode::ADD { vx, byte } => 0x7000 | vx << 8 | byte,
+        OpCode::LD { vx, other, by_value: false } => 0x8000 | xy(vx, other),
+        OpCode::OR { vx, vy } => 0x8001 | xy(vx, vy),
+        OpCode::AND { vx, vy } => 0x8002 | xy(vx, vy),
+        OpCode::XOR { vx, vy } => 0x8003 | xy(vx, vy),
+        OpCode::ADDREG { vx, vy } => 0x8004 | xy(vx, vy),
+        OpCode::SUB { vx, vy } => 0x8005 | xy(vx, vy),
+        OpCode::SHR { vx, vy } => 0x8006 | xy(vx, vy),
+        OpCode::SUBN { vx, vy } => 0x8007 | xy(vx, vy),
+        OpCode::SHL { vx, vy } => 0x800E | xy(vx, vy),
+        OpCode::SNE { vx, other, by_value: false } => 0x9000 | xy(vx, other),
+        OpCode::LDI { addr } => 0xA000 | addr,
+        OpCode::JPV0 { addr } => 0xB000 | addr,
+        OpCode::RND { vx, byte } => 0xC000 | vx << 8 | byte,
+        OpCode::DRW { vx, vy, nibble } => 0xD000 | xy(vx, vy) | nibble,
+        OpCode::SKP { vx } => 0xE09E | vx << 8,
+        OpCode::SKNP { vx } => 0xE0A1 | vx << 8,
+        OpCode::LDVXDT { vx } => 0xF007 | vx << 8,
+        OpCode::LDK { vx } => 0xF00A | vx << 8,
+        OpCode::LDDTVX { vx } => 0xF015 | vx << 8,
+        OpCode::LDSTVX { vx } => 0xF018 | vx << 8,
+        OpCode::ADDI { vx } => 0xF01E | vx << 8,
+        OpCode::LDF { vx } => 0xF029 | vx << 8,
+        OpCode::LDB { vx } => 0xF033 | vx << 8,
+        OpCode::LDMEMI { vx } => 0xF055 | vx << 8,
+        OpCode::LDVXMEMI { vx } => 0xF065 | vx << 8,
+    };
+
+    Some(word)
+}
+
+// Every word decodes to something, and everything but NOOP decodes to the
+// instruction it was encoded from.
+fuzz_target!(|word: u16| {
+    let opcode: OpCode = Instruction::from(word).into();
+
+    assert!(!opcode.name().is_empty());
+    assert!(!opcode.to_string().is_empty());
+
+    if let Some(encoded) = encode(&opcode) {
+        assert_eq!(word, encoded, "{:04X} decoded to {:?}", word, opcode);
+    }
+});
@@ -0,0 +1,41 @@
+#![no_main]
+
+use chip8::chip8::{Chip8, Clock};
+use libfuzzer_sys::fuzz_target;
+
+use std::collections::HashMap;
+
+const CYCLES: usize = 10_000;
+const CYCLES_PER_INPUT: usize = 100;
+
+// Runs an arbitrary ROM for a bounded number of cycles, changing the held keys
+// every hundred cycles. Faults are fine, panics are not.
+fuzz_target!(|input: (Vec<u8>, Vec<u16>)| {
+    let (rom, keys) = input;
+
+    let mut chip8 = Chip8::builder().program(rom).clock(Clock::Cycles(20)).seed(0).build().unwrap();
+
+    let mut keyboard: HashMap<u8, bool> = (0..16).map(|key| (key, false)).collect();
+    let mut held = 0u16;
+
+    for cycle in 0..CYCLES {
+        let mut keydown = None;
+
+        if !keys.is_empty() && cycle % CYCLES_PER_INPUT == 0 {
+            let mask = keys[cycle / CYCLES_PER_INPUT % keys.len()];
+            let pressed = mask & !held;
+
+            if pressed != 0 {
+                keydown = Some(pressed.trailing_zeros() as u8);
+            }
+            for key in 0..16u8 {
+                keyboard.insert(key, mask & 1 << key != 0);
+            }
+            held = mask;
+        }
+
+        if chip8.step(&keyboard, keydown).is_err() {
+            break;
+        }
+    }
+});
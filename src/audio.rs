@@ -0,0 +1,305 @@
+//! The beeper, synthesized one emulated frame at a time. The sound timer only
+//! changes on frame ticks, so gating whole frames is exact and independent of
+//! how fast the host loop runs.
+
+use crate::AudioSink;
+use crate::chip8::Chip8;
+
+use std::f32::consts::PI;
+use std::io::{self, Seek, SeekFrom, Write};
+
+const FRAMES_PER_SECOND: u64 = 60;
+// Envelope lengths in seconds, short enough for one-frame beeps but long
+// enough to avoid clicks when the tone starts and stops
+const ATTACK: f32 = 0.002;
+const RELEASE: f32 = 0.005;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    pub const NAMES: [&'static str; 3] = ["square", "sine", "triangle"];
+
+    pub fn parse(name: &str) -> Result<Waveform, String> {
+        match name {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            _ => Err(format!("unknown waveform '{}', expected one of {}", name, Waveform::NAMES.join(", "))),
+        }
+    }
+
+    /// The value at the given phase in [0, 1), between -1 and 1.
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (phase * 2.0 * PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Tone {
+    pub frequency: f32,
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone { frequency: 440.0, volume: 0.25, waveform: Waveform::Square }
+    }
+}
+
+pub struct Beeper {
+    tone: Tone,
+    sample_rate: u32,
+    phase: f32,
+    // The envelope, from 0 when silent to 1 at full volume
+    level: f32,
+    frame: u64,
+    gate: bool,
+    frames_rendered: u64,
+}
+
+impl Beeper {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        Beeper { tone, sample_rate, phase: 0.0, level: 0.0, frame: 0, gate: false, frames_rendered: 0 }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Feeds the machine state after an instruction. Returns the samples of the
+    /// previous frame once the frame counter moves on. The tone sounds for the
+    /// whole frame if the sound timer was running at any point during it.
+    pub fn step(&mut self, frame: u64, sound_on: bool) -> Option<Vec<f32>> {
+        if frame == self.frame {
+            self.gate |= sound_on;
+            return None;
+        }
+
+        let samples = self.render_frame(self.gate);
+        self.frame = frame;
+        self.gate = sound_on;
+
+        Some(samples)
+    }
+
+    /// Renders one frame worth of samples with the tone on or off.
+    pub fn render_frame(&mut self, gate: bool) -> Vec<f32> {
+        // Frames do not have to be a whole number of samples long, so count
+        // from the start to keep the total exact
+        let rate = self.sample_rate as u64;
+        let start = self.frames_rendered * rate / FRAMES_PER_SECOND;
+        self.frames_rendered += 1;
+        let end = self.frames_rendered * rate / FRAMES_PER_SECOND;
+
+        let sample_rate = self.sample_rate as f32;
+        let attack = 1.0 / (ATTACK * sample_rate);
+        let release = 1.0 / (RELEASE * sample_rate);
+
+        (start..end).map(|_| {
+            self.level = if gate { (self.level + attack).min(1.0) } else { (self.level - release).max(0.0) };
+
+            if self.level == 0.0 {
+                self.phase = 0.0;
+                return 0.0;
+            }
+
+            let sample = self.tone.waveform.sample(self.phase) * self.tone.volume * self.level;
+            self.phase = (self.phase + self.tone.frequency / sample_rate) % 1.0;
+            sample
+        }).collect()
+    }
+}
+
+/// Runs the beeper from the machine's sound timer and passes its samples on.
+pub struct AudioOutput<S: AudioSink> {
+    beeper: Beeper,
+    sink: S,
+}
+
+impl<S: AudioSink> AudioOutput<S> {
+    pub fn new(tone: Tone, sink: S) -> Self {
+        AudioOutput { beeper: Beeper::new(tone, sink.sample_rate()), sink }
+    }
+
+    /// Called after every executed instruction.
+    pub fn record(&mut self, chip8: &Chip8) -> io::Result<()> {
+        match self.beeper.step(chip8.frame(), chip8.timers().1 > 0) {
+            Some(samples) => self.sink.write_samples(&samples),
+            None => Ok(()),
+        }
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+}
+
+/// Throws samples away, for when there is nowhere to play them.
+pub struct NullSink {
+    sample_rate: u32,
+}
+
+impl NullSink {
+    pub fn new(sample_rate: u32) -> Self {
+        NullSink { sample_rate }
+    }
+}
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write_samples(&mut self, _samples: &[f32]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes samples to a 16-bit mono PCM WAV file.
+pub struct WavSink<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    data_length: u32,
+}
+
+impl WavSink<io::BufWriter<std::fs::File>> {
+    pub fn create(filename: &str, sample_rate: u32) -> io::Result<Self> {
+        WavSink::new(io::BufWriter::new(std::fs::File::create(filename)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        write_wav_header(&mut writer, sample_rate, 0)?;
+        Ok(WavSink { writer, sample_rate, data_length: 0 })
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.finish()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+
+        self.data_length += (samples.len() * 2) as u32;
+        Ok(())
+    }
+
+    /// Fills in the lengths in the header, which are unknown until the end.
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.writer, self.sample_rate, self.data_length)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+fn write_wav_header<W: Write>(writer: &mut W, sample_rate: u32, data_length: u32) -> io::Result<()> {
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_length).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM, one channel
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+    // Bytes per sample frame, bits per sample
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_length.to_le_bytes())
+}
+
+#[test]
+fn test_render_frame() {
+    let mut beeper = Beeper::new(Tone::default(), 44100);
+
+    let silence = beeper.render_frame(false);
+    assert_eq!(735, silence.len());
+    assert!(silence.iter().all(|sample| *sample == 0.0));
+
+    // Fades in instead of jumping straight to full volume
+    let tone = beeper.render_frame(true);
+    assert!(tone[0] > 0.0 && tone[0] < 0.01);
+    assert_eq!(0.25, tone.iter().cloned().fold(0.0, f32::max));
+
+    let release = beeper.render_frame(false);
+    assert!(release[0] != 0.0);
+    assert_eq!(0.0, *release.last().unwrap());
+
+    // 1000 samples a second do not divide into frames evenly
+    let mut beeper = Beeper::new(Tone::default(), 1000);
+    let lengths: Vec<usize> = (0..3).map(|_| beeper.render_frame(false).len()).collect();
+    assert_eq!(vec!(16, 17, 17), lengths);
+}
+
+#[test]
+fn test_step() {
+    let mut beeper = Beeper::new(Tone { frequency: 100.0, volume: 1.0, waveform: Waveform::Triangle }, 6000);
+
+    assert_eq!(None, beeper.step(0, false));
+    assert_eq!(None, beeper.step(0, true));
+    assert_eq!(None, beeper.step(0, false));
+
+    // The sound timer ran during frame 0, so all of it is gated on
+    let samples = beeper.step(1, false).unwrap();
+    assert_eq!(100, samples.len());
+    assert!(samples.iter().any(|sample| *sample > 0.9));
+
+    let samples = beeper.step(2, false).unwrap();
+    assert_eq!(0.0, *samples.last().unwrap());
+
+    assert_eq!(Ok(Waveform::Sine), Waveform::parse("sine"));
+    assert!(Waveform::parse("sawtooth").is_err());
+}
+
+#[test]
+fn test_wav_output() {
+    use crate::chip8::Clock;
+
+    // LD V0, 2; LD ST, V0; JP 0x204
+    let mut chip8 = Chip8::builder().clock(Clock::Cycles(10)).program(vec!(0x60, 0x02, 0xF0, 0x18, 0x12, 0x04)).build().unwrap();
+    let sink = WavSink::new(io::Cursor::new(vec!()), 600).unwrap();
+    let mut audio = AudioOutput::new(Tone::default(), sink);
+
+    for _ in 0..40 {
+        chip8.step(&Default::default(), None).unwrap();
+        audio.record(&chip8).unwrap();
+    }
+
+    let wav = audio.into_sink().into_inner().unwrap().into_inner();
+    assert_eq!(b"RIFF", &wav[0..4]);
+    assert_eq!(&(36 + 80u32).to_le_bytes(), &wav[4..8]);
+    assert_eq!(&600u32.to_le_bytes(), &wav[24..28]);
+
+    // Four whole frames of ten samples, the first two with the tone on
+    let samples: Vec<i16> = wav[44..].chunks(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])).collect();
+    assert_eq!(40, samples.len());
+    assert!(samples[..20].iter().any(|sample| *sample != 0));
+    assert!(samples[30..].iter().all(|sample| *sample == 0));
+}
@@ -0,0 +1,64 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use chip8::testrom::{run, test_roms, Verdict};
+
+const CYCLES: u64 = 10_000;
+
+fn generate(directory: &str) {
+    if let Err(e) = fs::create_dir_all(directory) {
+        println!("{}: {}", directory, e);
+        process::exit(2);
+    }
+
+    for (number, test) in test_roms().iter().enumerate() {
+        let path = Path::new(directory).join(format!("{:02}-{}.ch8", number + 1, test.name));
+
+        if let Err(e) = fs::write(&path, &test.rom) {
+            println!("{}: {}", path.display(), e);
+            process::exit(2);
+        }
+        println!("{}", path.display());
+    }
+}
+
+fn check(filenames: &[String]) {
+    let mut failed = 0;
+
+    for filename in filenames {
+        let rom = match fs::read(filename) {
+            Ok(rom) => rom,
+            Err(e) => {
+                println!("{}: {}", filename, e);
+                process::exit(2);
+            }
+        };
+
+        let verdict = run(&rom, CYCLES);
+        if verdict != Verdict::Pass {
+            failed += 1;
+        }
+        println!("{} ... {:?}", filename, verdict);
+    }
+
+    if failed > 0 {
+        println!("{} of {} test ROMs did not pass", failed, filenames.len());
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(|command| command.as_str()) {
+        Some("generate") if args.len() == 3 => generate(&args[2]),
+        Some("check") if args.len() > 2 => check(&args[2..]),
+        _ => {
+            println!("Usage: {} generate directory", args[0]);
+            println!("       {} check rom...", args[0]);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::process;
+
+use chip8::trace::first_divergence;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 3 {
+        println!("Usage: {} left.trace right.trace", args[0]);
+        process::exit(2);
+    }
+
+    let open = |filename: &String| match File::open(filename) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            println!("{}: {}", filename, e);
+            process::exit(2);
+        }
+    };
+
+    let divergence = match first_divergence(open(&args[1]), open(&args[2])) {
+        Ok(divergence) => divergence,
+        Err(e) => {
+            println!("could not read traces: {}", e);
+            process::exit(2);
+        }
+    };
+
+    match divergence {
+        None => println!("traces are identical"),
+        Some(divergence) => {
+            let left = divergence.left.unwrap_or_else(|| "<end of trace>".to_string());
+            let right = divergence.right.unwrap_or_else(|| "<end of trace>".to_string());
+
+            let marker: String = left.chars().zip(right.chars())
+                .map(|(l, r)| if l == r { ' ' } else { '^' })
+                .collect();
+
+            println!("traces diverge at line {}", divergence.line);
+            println!("< {}", left);
+            println!("> {}", right);
+            println!("  {}", marker.trim_end());
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! A tiny 3x5 pixel font for drawing text into the SDL window without
+//! pulling in a font rendering library. Lowercase letters are drawn as
+//! uppercase, and anything without a glyph as `?`.
+
+pub const WIDTH: usize = 3;
+pub const HEIGHT: usize = 5;
+/// Horizontal distance between the starts of two characters.
+pub const ADVANCE: usize = WIDTH + 1;
+
+/// The rows of a character, top first. Bit 2 is the leftmost pixel.
+pub fn glyph(c: char) -> [u8; HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+/// The lit pixels of a line of text, as (x, y) offsets from its top left corner.
+pub fn pixels(text: &str) -> Vec<(usize, usize)> {
+    let mut pixels = vec!();
+
+    for (index, c) in text.chars().enumerate() {
+        for (y, row) in glyph(c).iter().enumerate() {
+            for x in 0..WIDTH {
+                if row & (0b100 >> x) != 0 {
+                    pixels.push((index * ADVANCE + x, y));
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+pub fn text_width(text: &str) -> usize {
+    (text.chars().count() * ADVANCE).saturating_sub(1)
+}
+
+#[test]
+fn test_pixels() {
+    assert_eq!(vec!((1, 0), (0, 1), (1, 1), (1, 2), (1, 3), (0, 4), (1, 4), (2, 4)), pixels("1"));
+    assert_eq!(vec!((5, 1), (5, 3)), pixels(" :"));
+    assert_eq!(glyph('A'), glyph('a'));
+    assert_eq!(7, text_width("AB"));
+}
@@ -1,16 +1,48 @@
 use crate::opcode::OpCode;
 use crate::instruction::Instruction;
+use crate::profile::Profile;
+use crate::coverage::Coverage;
+use crate::heatmap::Heatmap;
+use crate::quirks::{Platform, Quirks};
 extern crate rand;
 
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
 use std::fmt;
 use std::time::Instant;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+
+const STACK_DEPTH: usize = 16;
+const HISTORY_LENGTH: usize = 64;
+const MEMORY_SIZE: usize = 4096;
+const LOAD_ADDRESS: u16 = 0x200;
+
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
 
 pub struct State {
     pub display: [u8; 64*32],
     pub update_display: bool,
     pub play_audio: bool,
     pub waiting_for_input: bool,
+    pub frame: u64,
 }
 
 impl fmt::Display for State {
@@ -27,20 +59,74 @@ impl fmt::Display for State {
     }
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Fault {
+    StackUnderflow,
+    StackOverflow,
+    MemoryOutOfBounds(u16),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackUnderflow => write!(fmt, "stack underflow"),
+            Fault::StackOverflow => write!(fmt, "stack overflow"),
+            Fault::MemoryOutOfBounds(addr) => write!(fmt, "memory access out of bounds at 0x{:04X}", addr),
+        }
+    }
+}
+
+/// What drives the 60 Hz delay and sound timers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Clock {
+    /// Timers tick on wall-clock time.
+    RealTime,
+    /// Timers tick once every given number of executed cycles, which makes a
+    /// run reproducible.
+    Cycles(u32),
+}
+
+/// A plain copy of everything a program can observe, for tools that save,
+/// compare or patch machine state.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MachineState {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub memory: Vec<u8>,
+    pub display: Vec<u8>,
+}
+
 #[derive(Clone)]
 pub struct Chip8 {
-    v: [u8; 16],
-    i: u16,
-    delay_timer: u8,
-    sound_timer: u8,
-    pc: u16,
-    sp: u8,
-    stack: Vec<u16>,
-    display: [u8; 64*32],
-    memory: [u8; 4096],
-    last_updated: Instant,
-    update_display: bool,
-    waiting_for_input_vx: Option<u8>,
+    pub(crate) v: [u8; 16],
+    pub(crate) i: u16,
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    pub(crate) pc: u16,
+    pub(crate) stack: Vec<u16>,
+    pub(crate) stack_depth: usize,
+    pub(crate) display: [u8; 64*32],
+    pub(crate) memory: Vec<u8>,
+    pub(crate) initial_memory: Vec<u8>,
+    pub(crate) load_address: u16,
+    pub(crate) last_updated: Instant,
+    pub(crate) clock: Clock,
+    pub(crate) cycles: u32,
+    pub(crate) rng: StdRng,
+    pub(crate) seed: u64,
+    pub(crate) quirks: Quirks,
+    pub(crate) update_display: bool,
+    pub(crate) waiting_for_input_vx: Option<u8>,
+    pub(crate) last_executed: Option<(u16, u16)>,
+    pub(crate) history: VecDeque<(u16, u16)>,
+    pub(crate) frame: u64,
+    pub(crate) profile: Option<Box<Profile>>,
+    pub(crate) coverage: Option<Box<Coverage>>,
+    pub(crate) heatmap: Option<Box<Heatmap>>,
 }
 
 impl fmt::Debug for Chip8 {
@@ -51,159 +137,268 @@ impl fmt::Debug for Chip8 {
            .field("delay_timer", &self.delay_timer)
            .field("sound_timer", &self.sound_timer)
            .field("pc", &self.pc)
-           .field("sp", &self.sp)
            .field("stack", &self.stack)
+           .field("memory", &&self.memory[..])
+           .field("display", &&self.display[..])
            .finish()
     }
 }
 
 impl Chip8 {
+    /// A machine with the default configuration and `program` loaded at 0x200.
     pub fn new_program(program: Vec<u8>) -> Chip8 {
-        let mut memory = [0; 4096];
+        Chip8Builder::new().program(program).build().expect("the default configuration is valid")
+    }
+
+    pub fn builder() -> Chip8Builder {
+        Chip8Builder::new()
+    }
+
+    pub fn step(&mut self, keyboard: &HashMap<u8, bool>, keydown: Option<u8>) -> Result<State, Fault> {
+        self.last_executed = None;
 
-        for (i, data) in program.iter().enumerate() {
-            memory[i + 0x200] = *data;
+        if let Some(key) = keydown {
+            if let Some(vx) = self.waiting_for_input_vx {
+                self.v[vx as usize] = key;
+                self.waiting_for_input_vx = None;
+            }
         }
 
-        let fonts = vec!(
-            0xF0, // 0
-            0x90,
-            0x90,   
-            0x90,
-            0xF0,
-            0x20, // 1
-            0x60,
-            0x20,
-            0x20,
-            0x70,
-            0xF0, // 2
-            0x10,
-            0xF0,
-            0x80,
-            0xF0,
-            0xF0, // 3
-            0x10,
-            0xF0,
-            0x10,
-            0xF0,
-            0x90,
-            0x90,
-            0xF0,
-            0x10,
-            0x10,
-            0xF0,
-            0x80,
-            0xF0,
-            0x10,
-            0xF0,
-            0xF0,
-            0x80,
-            0xF0,
-            0x90,
-            0xF0,
-            0xF0,
-            0x10,
-            0x20,
-            0x40,
-            0x40,
-            0xF0,
-            0x90,
-            0xF0,
-            0x90,
-            0xF0,
-            0xF0,
-            0x90,
-            0xF0,
-            0x10,
-            0xF0,
-            0xF0,
-            0x90,
-            0xF0,
-            0x90,
-            0x90,
-            0xE0,
-            0x90,
-            0xE0,
-            0x90,
-            0xE0,
-            0xF0,
-            0x80,
-            0x80,
-            0x80,
-            0xF0,
-            0xE0,
-            0x90,
-            0x90,
-            0x90,
-            0xE0,
-            0xF0,
-            0x80,
-            0xF0,
-            0x80,
-            0xF0,
-            0xF0,
-            0x80,
-            0xF0,
-            0x80,
-            0x80,
-        );
-
-        for (i, data) in fonts.iter().enumerate() {
-            memory[i] = *data;
+        let waiting_for_input = self.waiting_for_input_vx.is_some();
+
+        if !waiting_for_input {
+            self.execute(keyboard)?;
         }
 
-        Chip8 {
-            v: [0; 16],
-            i: 0,
-            delay_timer: 0,
-            sound_timer: 0,
-            pc: 0x200,
-            sp: 0,
-            stack: vec!(),
-            display: [0; 64 * 32],
-            memory: memory,
-            last_updated: Instant::now(),
-            update_display: false,
-            waiting_for_input_vx: None,
+        self.advance_clock();
+
+        let update_display = self.update_display;
+        self.update_display = false;
+
+        Ok(State { display: self.display, update_display, play_audio: self.sound_timer > 0, waiting_for_input, frame: self.frame })
+    }
+
+    fn execute(&mut self, keyboard: &HashMap<u8, bool>) -> Result<(), Fault> {
+        if self.pc as usize + 1 >= self.memory.len() {
+            return Err(Fault::MemoryOutOfBounds(self.pc));
+        }
+
+        let opcode = self.read_opcode();
+        let executed = (self.pc, self.read_word(self.pc));
+
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
         }
+        self.history.push_back(executed);
+        self.last_executed = Some(executed);
+
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record_instruction(executed.0, &opcode);
+        }
+
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark_executed(executed.0);
+        }
+
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            heatmap.record_execute(executed.0);
+        }
+
+        self.apply(opcode, keyboard)
     }
 
-    pub fn step(&mut self, keyboard: &HashMap<u8, bool>, keydown: Option<u8>) -> State {
-        if let Some(key) = keydown {
-            if self.waiting_for_input_vx != None {
-                self.v[self.waiting_for_input_vx.unwrap() as usize] = key;
-                self.waiting_for_input_vx = None;
-            }
+    fn advance_clock(&mut self) {
+        match self.clock {
+            Clock::RealTime => {
+                if self.last_updated.elapsed().as_millis() > 1000/60 {
+                    self.last_updated = Instant::now();
+                    self.tick_frame();
+                }
+            },
+            Clock::Cycles(cycles_per_frame) => {
+                self.cycles += 1;
+                if self.cycles >= cycles_per_frame {
+                    self.cycles = 0;
+                    self.tick_frame();
+                }
+            },
         }
+    }
+
+    /// Restarts the program from scratch: memory goes back to the ROM image it
+    /// was built with, and the CPU, timers and display are cleared.
+    pub fn reset(&mut self) {
+        self.memory.copy_from_slice(&self.initial_memory);
+        self.display = [0; 64 * 32];
+        self.soft_reset();
 
-        if self.waiting_for_input_vx != None {
-            return State { display: self.display, update_display: false, play_audio: self.sound_timer > 0, waiting_for_input: true };
+        self.frame = 0;
+        self.cycles = 0;
+        self.last_updated = Instant::now();
+        self.history.clear();
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+
+    /// Resets the CPU, stack and timers but keeps memory and the display, like
+    /// jumping back to the load address.
+    pub fn soft_reset(&mut self) {
+        self.v = [0; 16];
+        self.i = 0;
+        self.pc = self.load_address;
+        self.stack.clear();
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.waiting_for_input_vx = None;
+        self.last_executed = None;
+        self.update_display = true;
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+        self.cycles = 0;
+        self.last_updated = Instant::now();
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn set_register(&mut self, register: usize, value: u8) {
+        self.v[register] = value;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Return addresses, innermost call last. Each entry is the address of the CALL instruction.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// The delay and sound timers.
+    pub fn timers(&self) -> (u8, u8) {
+        (self.delay_timer, self.sound_timer)
+    }
+
+    pub fn set_timers(&mut self, delay_timer: u8, sound_timer: u8) {
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    pub fn display(&self) -> &[u8] {
+        &self.display
+    }
+
+    pub fn snapshot(&self) -> MachineState {
+        MachineState {
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            stack: self.stack.clone(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            memory: self.memory.to_vec(),
+            display: self.display.to_vec(),
         }
+    }
 
-        if self.last_updated.elapsed().as_millis() > 1000/60 {
-            self.last_updated = Instant::now();
+    /// Puts the machine back into a snapshotted state. Memory and display
+    /// contents beyond what the snapshot holds are left alone.
+    pub fn restore(&mut self, state: &MachineState) {
+        self.v = state.v;
+        self.i = state.i;
+        self.pc = state.pc;
+        self.stack = state.stack.clone();
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
 
-            if self.delay_timer > 0 {
-                self.delay_timer -= 1;
-            }
+        let memory = self.memory.len().min(state.memory.len());
+        self.memory[..memory].copy_from_slice(&state.memory[..memory]);
+        let display = self.display.len().min(state.display.len());
+        self.display[..display].copy_from_slice(&state.display[..display]);
+        self.update_display = true;
+    }
 
-            if self.sound_timer > 0 {
-                self.sound_timer -= 1;
-            }
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub(crate) fn tick_frame(&mut self) {
+        self.frame += 1;
+
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
         }
 
-        let opcode = self.read_opcode();
-        self.apply(opcode, keyboard);
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
 
-        let update_display = self.update_display;
-        self.update_display = false;
+        if let Some(profile) = self.profile.as_mut() {
+            profile.end_frame(self.waiting_for_input_vx.is_some());
+        }
+    }
+
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Box::new(Profile::new(self.memory.len())));
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_deref()
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Box::new(Coverage::new(self.memory.len())));
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_deref()
+    }
+
+    pub fn enable_heatmap(&mut self) {
+        self.heatmap = Some(Box::new(Heatmap::new(self.memory.len())));
+    }
 
-        State { display: self.display, update_display, play_audio: self.sound_timer > 0, waiting_for_input: false }
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_deref()
     }
 
     pub fn read_opcode(&self) -> OpCode {
-        let instruction = ((self.memory[self.pc as usize] as u16) << 8) 
-            + self.memory[(self.pc + 1) as usize] as u16;
+        self.read_opcode_at(self.pc)
+    }
+
+    pub fn read_opcode_at(&self, addr: u16) -> OpCode {
+        let instruction = self.read_word(addr);
 
         let opcode = Instruction::from(instruction).into();
 
@@ -212,88 +407,159 @@ impl Chip8 {
         opcode
     }
 
-    pub fn apply(&mut self, opcode: OpCode, keyboard: &HashMap<u8, bool>) {
+    pub fn read_word(&self, addr: u16) -> u16 {
+        ((self.memory[addr as usize] as u16) << 8) + self.memory[(addr + 1) as usize] as u16
+    }
+
+    /// The address and raw instruction word executed by the last call to `step`,
+    /// or `None` if it was blocked waiting for input.
+    pub fn last_executed(&self) -> Option<(u16, u16)> {
+        self.last_executed
+    }
+
+    /// The most recently executed instructions as (address, raw word) pairs, oldest first.
+    pub fn history(&self) -> &VecDeque<(u16, u16)> {
+        &self.history
+    }
+
+    fn address(&self, addr: u16) -> Result<usize, Fault> {
+        if (addr as usize) < self.memory.len() {
+            Ok(addr as usize)
+        } else {
+            Err(Fault::MemoryOutOfBounds(addr))
+        }
+    }
+
+    fn read_data(&mut self, addr: u16) -> Result<u8, Fault> {
+        let addr = self.address(addr)?;
+
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark_data(addr as u16);
+        }
+
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            heatmap.record_read(addr as u16);
+        }
+
+        Ok(self.memory[addr])
+    }
+
+    fn write_data(&mut self, addr: u16, value: u8) -> Result<(), Fault> {
+        let addr = self.address(addr)?;
+
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            heatmap.record_write(addr as u16);
+        }
+
+        self.memory[addr] = value;
+
+        Ok(())
+    }
+
+    pub fn apply(&mut self, opcode: OpCode, keyboard: &HashMap<u8, bool>) -> Result<(), Fault> {
         match opcode {
             OpCode::NOOP => {},
             OpCode::CLS => {
                 self.display.iter_mut().for_each(|x| *x = 0);
                 self.update_display = true;
             },
-            OpCode::RET => self.pc = self.stack.pop().unwrap(),
-            OpCode::JP { addr } => self.pc = addr - 2,
+            OpCode::RET => self.pc = self.stack.pop().ok_or(Fault::StackUnderflow)?,
+            OpCode::JP { addr } => self.pc = addr.wrapping_sub(2),
             OpCode::CALL { addr } => {
+                if self.stack.len() == self.stack_depth {
+                    return Err(Fault::StackOverflow);
+                }
+
                 self.stack.push(self.pc); 
-                self.pc = addr - 2;
+                self.pc = addr.wrapping_sub(2);
             },
             OpCode::SE { vx, other, by_value } => {
                 let value = if by_value { other as u8 } else { self.v[other as usize] };
                 if self.v[vx as usize] == value {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
             },
             OpCode::SNE { vx, other, by_value } => {
                 let value = if by_value { other as u8 } else { self.v[other as usize] };
                 if self.v[vx as usize] != value {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
             },
             OpCode::LD { vx, other, by_value } => {
                 let value = if by_value { other as u8 } else { self.v[other as usize] };
                 self.v[vx as usize] = value;
             },
-            OpCode::ADD { vx, byte } => self.v[vx as usize] = (self.v[vx as usize] as u16 + (byte & 0xFF) as u16) as u8,
-            OpCode::OR { vx, vy } => self.v[vx as usize] = self.v[vx as usize] | self.v[vy as usize],
-            OpCode::AND { vx, vy } => self.v[vx as usize] = self.v[vx as usize] & self.v[vy as usize],
-            OpCode::XOR { vx, vy } => self.v[vx as usize] = self.v[vx as usize] ^ self.v[vy as usize],
-            OpCode::ADDREG { vx, vy } => {
-                let sum = self.v[vx as usize] as u16 + self.v[vy as usize] as u16;
-                self.v[0xF] = if sum > 255 { 1 } else { 0 };
-
-                self.v[vx as usize] = sum as u8;
-            },
-            OpCode::SUB { vx, vy } => {
-                if self.v[vx as usize] > self.v[vy as usize] {
-                    self.v[0xF] = 1;
-                    self.v[vx as usize] = self.v[vx as usize] - self.v[vy as usize];
-                } else {
+            OpCode::ADD { vx, byte } => self.v[vx as usize] = (self.v[vx as usize] as u16 + (byte & 0xFF)) as u8,
+            OpCode::OR { vx, vy } => {
+                self.v[vx as usize] |= self.v[vy as usize];
+                if self.quirks.logic_resets_vf {
                     self.v[0xF] = 0;
-                    self.v[vx as usize] = (self.v[vx as usize] as i16 - self.v[vy as usize] as i16) as u8;
                 }
             },
-            OpCode::SHR { vx, vy: _ } => {
-                self.v[0xF] = self.v[vx as usize] & 0b00000001;
-                self.v[vx as usize] = self.v[vx as usize] >> 1;
+            OpCode::AND { vx, vy } => {
+                self.v[vx as usize] &= self.v[vy as usize];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
             },
-            OpCode::SUBN { vx, vy } => {
-                if self.v[vy as usize] > self.v[vx as usize] {
-                    self.v[0xF] = 1;
-                    self.v[vx as usize] = self.v[vy as usize] - self.v[vx as usize];
-                } else {
+            OpCode::XOR { vx, vy } => {
+                self.v[vx as usize] ^= self.v[vy as usize];
+                if self.quirks.logic_resets_vf {
                     self.v[0xF] = 0;
-                    self.v[vx as usize] = (self.v[vy as usize] as i16 - self.v[vx as usize] as i16) as u8;
                 }
             },
-            OpCode::SHL { vx, vy: _ } => {
-                self.v[0xF] = (self.v[vx as usize] & 0b10000000) >> 7;
-                self.v[vx as usize] = self.v[vx as usize] << 1;
+            // VF is always written last, so the flag wins when VF is also the destination
+            OpCode::ADDREG { vx, vy } => {
+                let (sum, carry) = self.v[vx as usize].overflowing_add(self.v[vy as usize]);
+                self.v[vx as usize] = sum;
+                self.v[0xF] = carry as u8;
+            },
+            OpCode::SUB { vx, vy } => {
+                let (x, y) = (self.v[vx as usize], self.v[vy as usize]);
+                self.v[vx as usize] = x.wrapping_sub(y);
+                self.v[0xF] = (x >= y) as u8;
+            },
+            OpCode::SHR { vx, vy } => {
+                let value = if self.quirks.shift_uses_vy { self.v[vy as usize] } else { self.v[vx as usize] };
+                self.v[vx as usize] = value >> 1;
+                self.v[0xF] = value & 0b00000001;
+            },
+            OpCode::SUBN { vx, vy } => {
+                let (x, y) = (self.v[vx as usize], self.v[vy as usize]);
+                self.v[vx as usize] = y.wrapping_sub(x);
+                self.v[0xF] = (y >= x) as u8;
+            },
+            OpCode::SHL { vx, vy } => {
+                let value = if self.quirks.shift_uses_vy { self.v[vy as usize] } else { self.v[vx as usize] };
+                self.v[vx as usize] = value << 1;
+                self.v[0xF] = (value & 0b10000000) >> 7;
             },
             OpCode::LDI { addr } => self.i = addr,
-            OpCode::JPV0 { addr } => self.pc = self.v[0] as u16 + addr - 2,
-            OpCode::RND { vx, byte } => self.v[vx as usize] = rand::random::<u8>() & byte as u8,
+            OpCode::JPV0 { addr } => {
+                let offset = if self.quirks.jump_uses_vx { self.v[(addr >> 8) as usize] } else { self.v[0] };
+                self.pc = (offset as u16 + addr).wrapping_sub(2);
+            },
+            OpCode::RND { vx, byte } => self.v[vx as usize] = self.rng.gen::<u8>() & byte as u8,
             OpCode::DRW { vx, vy, nibble } => {
-                let x = self.v[vx as usize] as usize;
-                let y = self.v[vy as usize] as usize;
+                let x = self.v[vx as usize] as usize % 64;
+                let y = self.v[vy as usize] as usize % 32;
 
                 self.v[0xF] = 0;
 
                 for yy in 0..nibble {
-                    let sprite_part = self.memory[(self.i + yy) as usize];
+                    let sprite_part = self.read_data(self.i.wrapping_add(yy))?;
+                    if self.quirks.clip_sprites && y + yy as usize >= 32 {
+                        break;
+                    }
                     let current_y = (y + yy as usize) % 32;
 
                     for xx in 0..8 {
+                        if self.quirks.clip_sprites && x + xx >= 64 {
+                            break;
+                        }
                         let current_x = (x + xx) % 64;
 
-                        let index = (current_y * 64 + current_x) as usize;
+                        let index = current_y * 64 + current_x;
 
                         let pixel = (sprite_part >> (7 - xx)) & 0b1;
 
@@ -304,40 +570,651 @@ impl Chip8 {
 
                 self.update_display = true;
             },
-            OpCode::SKP { vx } => if *keyboard.get(&self.v[vx as usize]).unwrap() { self.pc += 2 },
-            OpCode::SKNP { vx } => if !*keyboard.get(&self.v[vx as usize]).unwrap() { self.pc += 2 },
+            OpCode::SKP { vx } => if *keyboard.get(&self.v[vx as usize]).unwrap_or(&false) { self.pc = self.pc.wrapping_add(2) },
+            OpCode::SKNP { vx } => if !*keyboard.get(&self.v[vx as usize]).unwrap_or(&false) { self.pc = self.pc.wrapping_add(2) },
             OpCode::LDVXDT { vx } => self.v[vx as usize] = self.delay_timer,
             OpCode::LDK { vx } => {
                 self.waiting_for_input_vx = Some(vx as u8);
             },
             OpCode::LDDTVX { vx } => self.delay_timer = self.v[vx as usize],
             OpCode::LDSTVX { vx } => self.sound_timer = self.v[vx as usize],
-            OpCode::ADDI { vx } => self.i += self.v[vx as usize] as u16,
+            OpCode::ADDI { vx } => self.i = self.i.wrapping_add(self.v[vx as usize] as u16),
             OpCode::LDF { vx } => { 
-                let digit = self.v[vx as usize];
-                self.i = (digit * 5).into();
+                let digit = self.v[vx as usize] as u16;
+                self.i = digit * 5;
             },
             OpCode::LDB { vx } => { 
                 let value = self.v[vx as usize];
+                self.address(self.i.wrapping_add(2))?;
 
-                self.memory[self.i as usize] = value / 100;
-                self.memory[(self.i + 1) as usize] = (value % 100) / 10;
-                self.memory[(self.i + 2) as usize] = value % 10;
+                self.write_data(self.i, value / 100)?;
+                self.write_data(self.i.wrapping_add(1), (value % 100) / 10)?;
+                self.write_data(self.i.wrapping_add(2), value % 10)?;
             },
             OpCode::LDMEMI { vx } => { 
                 for i in 0..=vx {
-                    self.memory[(self.i + i) as usize] = self.v[i as usize];
+                    self.write_data(self.i.wrapping_add(i), self.v[i as usize])?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(vx + 1);
                 }
             },
             OpCode::LDVXMEMI { vx } => { 
                 for i in 0..=vx {
-                    self.v[i as usize] = self.memory[(self.i + i) as usize];
+                    self.v[i as usize] = self.read_data(self.i.wrapping_add(i))?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(vx + 1);
                 }
             }
         };
 
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
+
+        Ok(())
     }
 }
 
 
+
+/// Configures and creates a `Chip8`. Everything defaults to the original
+/// CHIP-8 layout: 4 KiB of memory, programs loaded at 0x200, the built-in font
+/// at 0x000, a 16 entry stack and real-time timers.
+#[derive(Debug, Clone)]
+pub struct Chip8Builder {
+    quirks: Quirks,
+    memory_size: usize,
+    load_address: u16,
+    font: Vec<u8>,
+    seed: Option<u64>,
+    stack_depth: usize,
+    clock: Clock,
+    program: Vec<u8>,
+}
+
+impl Chip8Builder {
+    pub fn new() -> Self {
+        Chip8Builder {
+            quirks: Quirks::default(),
+            memory_size: MEMORY_SIZE,
+            load_address: LOAD_ADDRESS,
+            font: FONT.to_vec(),
+            seed: None,
+            stack_depth: STACK_DEPTH,
+            clock: Clock::RealTime,
+            program: vec!(),
+        }
+    }
+
+    /// Uses the quirks of the given platform. Replaces any quirks set before.
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.quirks = platform.quirks();
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// At most 64 KiB, the most a 16-bit I register can address.
+    pub fn memory_size(mut self, memory_size: usize) -> Self {
+        self.memory_size = memory_size;
+        self
+    }
+
+    pub fn load_address(mut self, load_address: u16) -> Self {
+        self.load_address = load_address;
+        self
+    }
+
+    /// Hex digit sprites, five bytes per digit, stored from address 0.
+    pub fn font(mut self, font: &[u8]) -> Self {
+        self.font = font.to_vec();
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn stack_depth(mut self, stack_depth: usize) -> Self {
+        self.stack_depth = stack_depth;
+        self
+    }
+
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The program loaded at the load address. Anything that does not fit in memory is dropped.
+    pub fn program(mut self, program: Vec<u8>) -> Self {
+        self.program = program;
+        self
+    }
+
+    pub fn build(self) -> Result<Chip8, String> {
+        let load_address = self.load_address as usize;
+
+        if self.memory_size > 0x10000 {
+            return Err(format!("memory size {} is larger than 64 KiB", self.memory_size));
+        }
+        if load_address >= self.memory_size {
+            return Err(format!("load address 0x{:04X} is outside of memory", load_address));
+        }
+        if self.font.len() > load_address {
+            return Err(format!("font of {} bytes overlaps the load address 0x{:04X}", self.font.len(), load_address));
+        }
+
+        let mut memory = vec![0; self.memory_size];
+        memory[..self.font.len()].copy_from_slice(&self.font);
+
+        let length = self.program.len().min(self.memory_size - load_address);
+        memory[load_address..load_address + length].copy_from_slice(&self.program[..length]);
+
+        // Without a seed pick one anyway, so a hard reset replays the same random numbers
+        let seed = self.seed.unwrap_or_else(rand::random);
+
+        Ok(Chip8 {
+            v: [0; 16],
+            i: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            pc: self.load_address,
+            stack: vec!(),
+            stack_depth: self.stack_depth,
+            display: [0; 64 * 32],
+            initial_memory: memory.clone(),
+            memory,
+            load_address: self.load_address,
+            last_updated: Instant::now(),
+            clock: self.clock,
+            cycles: 0,
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            quirks: self.quirks,
+            update_display: false,
+            waiting_for_input_vx: None,
+            last_executed: None,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+            frame: 0,
+            profile: None,
+            coverage: None,
+            heatmap: None,
+        })
+    }
+}
+
+impl Default for Chip8Builder {
+    fn default() -> Self {
+        Chip8Builder::new()
+    }
+}
+
+#[cfg(test)]
+fn exec(chip8: &mut Chip8, word: u16) -> Result<(), Fault> {
+    exec_with_keys(chip8, word, &HashMap::new())
+}
+
+#[cfg(test)]
+fn exec_with_keys(chip8: &mut Chip8, word: u16, keyboard: &HashMap<u8, bool>) -> Result<(), Fault> {
+    chip8.apply(Instruction::from(word).into(), keyboard)
+}
+
+#[cfg(test)]
+fn with_registers(registers: &[(usize, u8)]) -> Chip8 {
+    let mut chip8 = Chip8::new_program(vec!());
+    for (register, value) in registers {
+        chip8.v[*register] = *value;
+    }
+    chip8
+}
+
+#[test]
+fn test_cls() {
+    let mut chip8 = Chip8::new_program(vec!());
+    chip8.display[100] = 1;
+
+    exec(&mut chip8, 0x00E0).unwrap();
+    assert!(chip8.display.iter().all(|pixel| *pixel == 0));
+    assert!(chip8.update_display);
+    assert_eq!(0x202, chip8.pc);
+}
+
+#[test]
+fn test_call_and_ret() {
+    let mut chip8 = Chip8::new_program(vec!());
+
+    exec(&mut chip8, 0x2400).unwrap();
+    assert_eq!((0x400, vec!(0x200)), (chip8.pc, chip8.stack.clone()));
+
+    exec(&mut chip8, 0x00EE).unwrap();
+    assert_eq!((0x202, vec!()), (chip8.pc, chip8.stack.clone()));
+
+    assert_eq!(Err(Fault::StackUnderflow), exec(&mut chip8, 0x00EE));
+
+    for _ in 0..STACK_DEPTH {
+        exec(&mut chip8, 0x2400).unwrap();
+    }
+    assert_eq!(Err(Fault::StackOverflow), exec(&mut chip8, 0x2400));
+}
+
+#[test]
+fn test_jp() {
+    let mut chip8 = Chip8::new_program(vec!());
+
+    exec(&mut chip8, 0x1ABC).unwrap();
+    assert_eq!(0xABC, chip8.pc);
+
+    exec(&mut chip8, 0x1000).unwrap();
+    assert_eq!(0x000, chip8.pc);
+}
+
+#[test]
+fn test_skips() {
+    let cases = [
+        (0x3A42, 0x204), (0x3A43, 0x202),
+        (0x4A42, 0x202), (0x4A43, 0x204),
+        (0x5AB0, 0x204), (0x5AC0, 0x202),
+        (0x9AB0, 0x202), (0x9AC0, 0x204),
+    ];
+
+    for (word, pc) in cases.iter() {
+        let mut chip8 = with_registers(&[(0xA, 0x42), (0xB, 0x42), (0xC, 0x43)]);
+        exec(&mut chip8, *word).unwrap();
+        assert_eq!(*pc, chip8.pc, "{:04X}", word);
+    }
+
+    // A skip from the last instruction of a 64 KiB memory wraps around to the start
+    let mut chip8 = Chip8::builder().memory_size(0x10000).build().unwrap();
+    chip8.pc = 0xFFFE;
+    chip8.memory[0xFFFE..].copy_from_slice(&[0x30, 0x00]);
+    chip8.step(&HashMap::new(), None).unwrap();
+    assert_eq!(0x0002, chip8.pc);
+}
+
+#[test]
+fn test_ld_and_add() {
+    let mut chip8 = with_registers(&[(0xF, 7)]);
+
+    exec(&mut chip8, 0x6AFF).unwrap();
+    exec(&mut chip8, 0x8BA0).unwrap();
+    assert_eq!((0xFF, 0xFF), (chip8.v[0xA], chip8.v[0xB]));
+
+    // 7XNN wraps and never touches VF
+    exec(&mut chip8, 0x7A02).unwrap();
+    assert_eq!((0x01, 7), (chip8.v[0xA], chip8.v[0xF]));
+    assert_eq!(0x206, chip8.pc);
+}
+
+#[test]
+fn test_logic() {
+    let cases = [(0x8011, 0b1110), (0x8012, 0b1000), (0x8013, 0b0110)];
+
+    for (word, result) in cases.iter() {
+        let mut chip8 = with_registers(&[(0, 0b1100), (1, 0b1010), (0xF, 5)]);
+        exec(&mut chip8, *word).unwrap();
+        assert_eq!((*result, 5), (chip8.v[0], chip8.v[0xF]), "{:04X}", word);
+
+        let mut chip8 = with_registers(&[(0, 0b1100), (1, 0b1010), (0xF, 5)]);
+        chip8.set_quirks(Quirks { logic_resets_vf: true, ..Quirks::default() });
+        exec(&mut chip8, *word).unwrap();
+        assert_eq!((*result, 0), (chip8.v[0], chip8.v[0xF]), "{:04X} with vf-reset", word);
+    }
+}
+
+#[test]
+fn test_addreg() {
+    let mut chip8 = with_registers(&[(0, 0xF0), (1, 0x0F)]);
+    exec(&mut chip8, 0x8014).unwrap();
+    assert_eq!((0xFF, 0), (chip8.v[0], chip8.v[0xF]));
+
+    exec(&mut chip8, 0x8014).unwrap();
+    assert_eq!((0x0E, 1), (chip8.v[0], chip8.v[0xF]));
+
+    // VX == VY
+    let mut chip8 = with_registers(&[(2, 0x80)]);
+    exec(&mut chip8, 0x8224).unwrap();
+    assert_eq!((0x00, 1), (chip8.v[2], chip8.v[0xF]));
+
+    // The carry flag overwrites the sum when VF is the destination
+    let mut chip8 = with_registers(&[(0xF, 0x01), (3, 0x02)]);
+    exec(&mut chip8, 0x8F34).unwrap();
+    assert_eq!(0, chip8.v[0xF]);
+
+    let mut chip8 = with_registers(&[(0xF, 0xFF), (3, 0x02)]);
+    exec(&mut chip8, 0x8F34).unwrap();
+    assert_eq!(1, chip8.v[0xF]);
+
+    // VF as the source is read before the flag is written
+    let mut chip8 = with_registers(&[(0, 0xFF), (0xF, 0x01)]);
+    exec(&mut chip8, 0x80F4).unwrap();
+    assert_eq!((0x00, 1), (chip8.v[0], chip8.v[0xF]));
+}
+
+#[test]
+fn test_sub() {
+    let mut chip8 = with_registers(&[(0, 5), (1, 3)]);
+    exec(&mut chip8, 0x8015).unwrap();
+    assert_eq!((2, 1), (chip8.v[0], chip8.v[0xF]));
+
+    exec(&mut chip8, 0x8015).unwrap();
+    assert_eq!((0xFF, 0), (chip8.v[0], chip8.v[0xF]));
+
+    // Equal operands do not borrow
+    let mut chip8 = with_registers(&[(0, 5), (1, 5)]);
+    exec(&mut chip8, 0x8015).unwrap();
+    assert_eq!((0, 1), (chip8.v[0], chip8.v[0xF]));
+
+    let mut chip8 = with_registers(&[(4, 9)]);
+    exec(&mut chip8, 0x8445).unwrap();
+    assert_eq!((0, 1), (chip8.v[4], chip8.v[0xF]));
+
+    let mut chip8 = with_registers(&[(0xF, 1), (2, 2)]);
+    exec(&mut chip8, 0x8F25).unwrap();
+    assert_eq!(0, chip8.v[0xF]);
+}
+
+#[test]
+fn test_subn() {
+    let mut chip8 = with_registers(&[(0, 3), (1, 5)]);
+    exec(&mut chip8, 0x8017).unwrap();
+    assert_eq!((2, 1), (chip8.v[0], chip8.v[0xF]));
+
+    let mut chip8 = with_registers(&[(0, 5), (1, 3)]);
+    exec(&mut chip8, 0x8017).unwrap();
+    assert_eq!((0xFE, 0), (chip8.v[0], chip8.v[0xF]));
+
+    let mut chip8 = with_registers(&[(0, 5), (1, 5)]);
+    exec(&mut chip8, 0x8017).unwrap();
+    assert_eq!((0, 1), (chip8.v[0], chip8.v[0xF]));
+
+    let mut chip8 = with_registers(&[(0xF, 1), (2, 2)]);
+    exec(&mut chip8, 0x8F27).unwrap();
+    assert_eq!(1, chip8.v[0xF]);
+}
+
+#[test]
+fn test_shifts() {
+    let mut chip8 = with_registers(&[(0, 0b1000_0001), (1, 0b0100_0000)]);
+    exec(&mut chip8, 0x8016).unwrap();
+    assert_eq!((0b0100_0000, 1), (chip8.v[0], chip8.v[0xF]));
+
+    exec(&mut chip8, 0x801E).unwrap();
+    assert_eq!((0b1000_0000, 0), (chip8.v[0], chip8.v[0xF]));
+
+    exec(&mut chip8, 0x801E).unwrap();
+    assert_eq!((0, 1), (chip8.v[0], chip8.v[0xF]));
+
+    // The flag wins when VF is shifted
+    let mut chip8 = with_registers(&[(0xF, 0b0000_0010)]);
+    exec(&mut chip8, 0x8F06).unwrap();
+    assert_eq!(0, chip8.v[0xF]);
+
+    let mut chip8 = with_registers(&[(0xF, 0b1000_0000)]);
+    exec(&mut chip8, 0x8F0E).unwrap();
+    assert_eq!(1, chip8.v[0xF]);
+
+    let mut chip8 = with_registers(&[(0, 0xFF), (1, 0b0000_0110)]);
+    chip8.set_quirks(Quirks { shift_uses_vy: true, ..Quirks::default() });
+    exec(&mut chip8, 0x8016).unwrap();
+    assert_eq!((0b0000_0011, 0, 0b0000_0110), (chip8.v[0], chip8.v[0xF], chip8.v[1]));
+    exec(&mut chip8, 0x801E).unwrap();
+    assert_eq!((0b0000_1100, 0), (chip8.v[0], chip8.v[0xF]));
+}
+
+#[test]
+fn test_ldi_and_jpv0() {
+    let mut chip8 = with_registers(&[(0, 0x10), (3, 0x20)]);
+
+    exec(&mut chip8, 0xA123).unwrap();
+    assert_eq!((0x123, 0x202), (chip8.i, chip8.pc));
+
+    exec(&mut chip8, 0xB300).unwrap();
+    assert_eq!(0x310, chip8.pc);
+
+    chip8.set_quirks(Quirks { jump_uses_vx: true, ..Quirks::default() });
+    exec(&mut chip8, 0xB300).unwrap();
+    assert_eq!(0x320, chip8.pc);
+}
+
+#[test]
+fn test_rnd() {
+    let mut chip8 = Chip8::new_program(vec!());
+    chip8.seed_rng(1);
+
+    for _ in 0..32 {
+        exec(&mut chip8, 0xC00F).unwrap();
+        assert_eq!(0, chip8.v[0] & 0xF0);
+    }
+
+    exec(&mut chip8, 0xC000).unwrap();
+    assert_eq!(0, chip8.v[0]);
+}
+
+#[test]
+fn test_drw() {
+    // Font digit 0 at (2, 3)
+    let mut chip8 = with_registers(&[(0, 2), (1, 3), (0xF, 1)]);
+    exec(&mut chip8, 0xD015).unwrap();
+    assert_eq!(0, chip8.v[0xF]);
+    assert!(chip8.update_display);
+    assert_eq!([1, 1, 1, 1, 0], chip8.display[3 * 64 + 2..3 * 64 + 7]);
+    assert_eq!([1, 0, 0, 1, 0], chip8.display[4 * 64 + 2..4 * 64 + 7]);
+
+    // Drawing it again erases it and reports a collision
+    exec(&mut chip8, 0xD015).unwrap();
+    assert_eq!(1, chip8.v[0xF]);
+    assert!(chip8.display.iter().all(|pixel| *pixel == 0));
+
+    // Sprites wrap around both edges, and start coordinates wrap too
+    let mut chip8 = with_registers(&[(0, 62 + 64), (1, 31)]);
+    exec(&mut chip8, 0xD012).unwrap();
+    assert_eq!([1, 1], chip8.display[31 * 64 + 62..31 * 64 + 64]);
+    assert_eq!([1, 1], chip8.display[31 * 64..31 * 64 + 2]);
+    assert_eq!([1, 0, 0, 1], [chip8.display[62], chip8.display[63], chip8.display[0], chip8.display[1]]);
+
+    let mut chip8 = with_registers(&[(0, 62), (1, 31)]);
+    chip8.set_quirks(Quirks { clip_sprites: true, ..Quirks::default() });
+    exec(&mut chip8, 0xD012).unwrap();
+    assert_eq!(2, chip8.display.iter().filter(|pixel| **pixel == 1).count());
+
+    let mut chip8 = Chip8::new_program(vec!());
+    chip8.i = 0xFFF;
+    assert_eq!(Err(Fault::MemoryOutOfBounds(0x1000)), exec(&mut chip8, 0xD002));
+}
+
+#[test]
+fn test_keys() {
+    let mut keyboard = HashMap::new();
+    keyboard.insert(0xA, true);
+
+    let mut chip8 = with_registers(&[(0, 0xA), (1, 0xB)]);
+    exec_with_keys(&mut chip8, 0xE09E, &keyboard).unwrap();
+    assert_eq!(0x204, chip8.pc);
+    exec_with_keys(&mut chip8, 0xE19E, &keyboard).unwrap();
+    assert_eq!(0x206, chip8.pc);
+    exec_with_keys(&mut chip8, 0xE0A1, &keyboard).unwrap();
+    assert_eq!(0x208, chip8.pc);
+    exec_with_keys(&mut chip8, 0xE1A1, &keyboard).unwrap();
+    assert_eq!(0x20C, chip8.pc);
+
+    // LD V5, K halts until a key goes down
+    let mut chip8 = Chip8::new_program(vec!(0xF5, 0x0A, 0x00, 0x00));
+    chip8.step(&keyboard, None).unwrap();
+    assert!(chip8.step(&keyboard, None).unwrap().waiting_for_input);
+    assert_eq!(0x202, chip8.pc);
+
+    assert!(!chip8.step(&keyboard, Some(0xC)).unwrap().waiting_for_input);
+    assert_eq!((0xC, 0x204), (chip8.v[5], chip8.pc));
+}
+
+#[test]
+fn test_timers() {
+    let mut chip8 = with_registers(&[(0, 60), (1, 3)]);
+
+    exec(&mut chip8, 0xF015).unwrap();
+    exec(&mut chip8, 0xF118).unwrap();
+    assert_eq!((60, 3), (chip8.delay_timer, chip8.sound_timer));
+
+    chip8.tick_frame();
+    exec(&mut chip8, 0xF207).unwrap();
+    assert_eq!((59, 2), (chip8.v[2], chip8.sound_timer));
+}
+
+#[test]
+fn test_addi_and_ldf() {
+    let mut chip8 = with_registers(&[(0, 0x10), (1, 0xF)]);
+    chip8.i = 0xFFF8;
+
+    exec(&mut chip8, 0xF01E).unwrap();
+    assert_eq!(0x0008, chip8.i);
+
+    exec(&mut chip8, 0xF129).unwrap();
+    assert_eq!(75, chip8.i);
+    assert_eq!([0xF0, 0x80, 0xF0, 0x80, 0x80], chip8.memory[75..80]);
+}
+
+#[test]
+fn test_ldb() {
+    for (value, digits) in [(0, [0, 0, 0]), (9, [0, 0, 9]), (255, [2, 5, 5])].iter() {
+        let mut chip8 = with_registers(&[(3, *value)]);
+        chip8.i = 0x300;
+
+        exec(&mut chip8, 0xF333).unwrap();
+        assert_eq!(*digits, chip8.memory[0x300..0x303]);
+        assert_eq!(0x300, chip8.i);
+    }
+
+    // Nothing is written when the last digit falls outside memory
+    let mut chip8 = with_registers(&[(3, 123)]);
+    chip8.i = 0xFFE;
+    assert_eq!(Err(Fault::MemoryOutOfBounds(0x1000)), exec(&mut chip8, 0xF333));
+    assert_eq!([0, 0], chip8.memory[0xFFE..]);
+
+    // With 64 KiB of memory I wraps around like it does for LD [I], Vx
+    let mut chip8 = Chip8::builder().memory_size(0x10000).build().unwrap();
+    chip8.v[3] = 123;
+    chip8.i = 0xFFFE;
+    exec(&mut chip8, 0xF333).unwrap();
+    assert_eq!([1, 2], chip8.memory[0xFFFE..]);
+    assert_eq!(3, chip8.memory[0]);
+}
+
+#[test]
+fn test_ldmemi_and_ldvxmemi() {
+    let mut chip8 = Chip8::new_program(vec!());
+    for register in 0..16 {
+        chip8.v[register] = register as u8 + 1;
+    }
+    chip8.i = 0x300;
+
+    exec(&mut chip8, 0xFF55).unwrap();
+    assert_eq!((1..=16).collect::<Vec<u8>>(), chip8.memory[0x300..0x310].to_vec());
+    assert_eq!(0, chip8.memory[0x310]);
+    assert_eq!(0x300, chip8.i);
+
+    chip8.v = [0; 16];
+    exec(&mut chip8, 0xFF65).unwrap();
+    assert_eq!(16, chip8.v[0xF]);
+    assert_eq!(0x300, chip8.i);
+
+    chip8.set_quirks(Quirks { load_store_increments_i: true, ..Quirks::default() });
+    exec(&mut chip8, 0xF265).unwrap();
+    assert_eq!(0x303, chip8.i);
+    exec(&mut chip8, 0xFF55).unwrap();
+    assert_eq!(0x313, chip8.i);
+
+    chip8.i = 0xFF8;
+    assert_eq!(Err(Fault::MemoryOutOfBounds(0x1000)), exec(&mut chip8, 0xFF65));
+}
+
+#[test]
+fn test_snapshot_and_restore() {
+    // LD V0, 0x05; CALL 0x206; JP 0x204; ADD V0, 0x01
+    let mut chip8 = Chip8::new_program(vec!(0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0x70, 0x01));
+    chip8.step(&HashMap::new(), None).unwrap();
+    chip8.step(&HashMap::new(), None).unwrap();
+
+    assert_eq!((5, 0x206, &[0x202][..]), (chip8.registers()[0], chip8.pc(), chip8.stack()));
+
+    let snapshot = chip8.snapshot();
+    chip8.set_register(0, 0x42);
+    chip8.set_pc(0x300);
+    chip8.set_timers(10, 20);
+    chip8.memory_mut()[0x400] = 0xAB;
+    assert_eq!((0x42, 0x300, (10, 20), 0xAB), (chip8.registers()[0], chip8.pc(), chip8.timers(), chip8.memory()[0x400]));
+
+    chip8.restore(&snapshot);
+    assert_eq!(snapshot, chip8.snapshot());
+    assert_eq!(0, chip8.memory()[0x400]);
+}
+
+#[test]
+fn test_builder() {
+    use crate::quirks::Platform;
+
+    let chip8 = Chip8::builder()
+        .platform(Platform::CosmacVip)
+        .memory_size(0x2000)
+        .load_address(0x600)
+        .font(&[0xAA; 5])
+        .stack_depth(2)
+        .program(vec!(0x12, 0x34))
+        .build()
+        .unwrap();
+
+    assert_eq!(Platform::CosmacVip.quirks(), chip8.quirks);
+    assert_eq!((0x2000, 0x600, 0x1234), (chip8.memory().len(), chip8.pc(), chip8.read_word(0x600)));
+    assert_eq!([0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0x00], chip8.memory()[..6]);
+
+    let mut chip8 = Chip8::builder().stack_depth(1).program(vec!(0x22, 0x02, 0x22, 0x02)).build().unwrap();
+    chip8.step(&HashMap::new(), None).unwrap();
+    assert_eq!(Err(Fault::StackOverflow), chip8.step(&HashMap::new(), None).map(|_| ()));
+
+    let random = |seed| {
+        let mut chip8 = Chip8::builder().seed(seed).program(vec!(0xC0, 0xFF)).build().unwrap();
+        chip8.step(&HashMap::new(), None).unwrap();
+        chip8.registers()[0]
+    };
+    assert_eq!(random(9), random(9));
+
+    assert!(Chip8::builder().memory_size(0x20000).build().is_err());
+    assert!(Chip8::builder().load_address(0x1000).build().is_err());
+    assert!(Chip8::builder().load_address(0x40).build().is_err());
+}
+
+#[test]
+fn test_reset() {
+    // LD V0, 0x07; LD I, 0x300; LD [I], V0; CLS; DRW V0, V0, 1; LD DT, V0; CALL 0x20E; JP 0x20E
+    let program = vec!(0x60, 0x07, 0xA3, 0x00, 0xF0, 0x55, 0x00, 0xE0, 0xD0, 0x01, 0xF0, 0x15, 0x22, 0x0E, 0x12, 0x0E);
+    let mut chip8 = Chip8::builder().program(program).clock(Clock::Cycles(1000)).build().unwrap();
+    for _ in 0..7 {
+        chip8.step(&HashMap::new(), None).unwrap();
+    }
+    assert_eq!((7, 1, 7), (chip8.memory()[0x300], chip8.stack().len(), chip8.timers().0));
+
+    let display = chip8.display;
+    chip8.soft_reset();
+    assert_eq!((0x200, 0, 0, 0), (chip8.pc(), chip8.registers()[0], chip8.stack().len(), chip8.timers().0));
+    assert_eq!(7, chip8.memory()[0x300]);
+    assert_eq!(display[..], chip8.display()[..]);
+
+    chip8.reset();
+    assert_eq!((0x200, 0), (chip8.pc(), chip8.memory()[0x300]));
+    assert!(chip8.display().iter().all(|pixel| *pixel == 0));
+    assert_eq!(0x6007, chip8.read_word(0x200));
+    assert_eq!((0, 0), (chip8.frame(), chip8.history().len()));
+
+    // RND V0, 0xFF; RND V1, 0xFF
+    let mut chip8 = Chip8::builder().program(vec!(0xC0, 0xFF, 0xC1, 0xFF)).build().unwrap();
+    let run = |chip8: &mut Chip8| {
+        chip8.reset();
+        chip8.step(&HashMap::new(), None).unwrap();
+        chip8.step(&HashMap::new(), None).unwrap();
+        chip8.v[..2].to_vec()
+    };
+    assert_eq!(run(&mut chip8), run(&mut chip8));
+}
@@ -0,0 +1,227 @@
+//! Animated GIF and APNG recordings of the display, one image per emulated
+//! frame. Runs of identical frames are stored once with a longer delay.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub(crate) const WIDTH: usize = 64;
+pub(crate) const HEIGHT: usize = 32;
+const FRAMES_PER_SECOND: u64 = 60;
+/// The largest clip scale, 1024x512 frames. Every frame is kept in memory until
+/// the clip is saved, so much larger frames quickly become unreasonable.
+pub const MAX_SCALE: usize = 16;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ClipFormat {
+    Gif,
+    Apng,
+}
+
+impl ClipFormat {
+    /// Picks the format from the file extension: `.gif`, or `.png`/`.apng`.
+    pub fn from_path(path: &str) -> Result<ClipFormat, String> {
+        let extension = Path::new(path).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("gif") => Ok(ClipFormat::Gif),
+            Some("png") | Some("apng") => Ok(ClipFormat::Apng),
+            _ => Err(format!("unknown clip format for {}, expected .gif, .png or .apng", path)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Palette {
+    pub background: [u8; 3],
+    pub foreground: [u8; 3],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette { background: [0, 0, 0], foreground: [255, 255, 255] }
+    }
+}
+
+impl Palette {
+    /// Parses `foreground,background` as two hex colors, e.g. `33ff66,000000`.
+    pub fn parse(text: &str) -> Result<Palette, String> {
+        let colors: Vec<[u8; 3]> = text.split(',').map(parse_color).collect::<Result<_, _>>()?;
+
+        match colors.as_slice() {
+            [foreground, background] => Ok(Palette { background: *background, foreground: *foreground }),
+            _ => Err(format!("invalid palette '{}', expected two colors like ffffff,000000", text)),
+        }
+    }
+
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        self.background.iter().chain(self.foreground.iter()).cloned().collect()
+    }
+}
+
+fn parse_color(text: &str) -> Result<[u8; 3], String> {
+    let text = text.trim().trim_start_matches('#');
+    let value = u32::from_str_radix(text, 16).ok().filter(|_| text.len() == 6).ok_or_else(|| format!("invalid color '{}'", text))?;
+
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+pub struct ClipRecorder {
+    palette: Palette,
+    scale: usize,
+    // Distinct consecutive displays and how many frames each was shown
+    frames: Vec<([u8; WIDTH * HEIGHT], u16)>,
+}
+
+impl ClipRecorder {
+    pub fn new(palette: Palette, scale: usize) -> Self {
+        ClipRecorder { palette, scale: scale.max(1), frames: vec!() }
+    }
+
+    /// Adds the display as shown for one emulated frame.
+    pub fn capture(&mut self, display: &[u8]) {
+        let mut image = [0; WIDTH * HEIGHT];
+        for (pixel, value) in image.iter_mut().zip(display) {
+            *pixel = (*value > 0) as u8;
+        }
+
+        match self.frames.last_mut() {
+            Some((last, count)) if *last == image && *count < u16::MAX => *count += 1,
+            _ => self.frames.push((image, 1)),
+        }
+    }
+
+    /// The number of emulated frames captured.
+    pub fn length(&self) -> u64 {
+        self.frames.iter().map(|(_, count)| *count as u64).sum()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let format = ClipFormat::from_path(path)?;
+        let file = File::create(path).map_err(|e| e.to_string())?;
+
+        match format {
+            ClipFormat::Gif => self.write_gif(BufWriter::new(file)),
+            ClipFormat::Apng => self.write_apng(BufWriter::new(file)),
+        }
+    }
+
+    pub fn write_gif<W: Write>(&self, writer: W) -> Result<(), String> {
+        self.check_scale()?;
+
+        let (width, height) = (WIDTH * self.scale, HEIGHT * self.scale);
+        let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &self.palette.to_bytes()).map_err(|e| e.to_string())?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+
+        // GIF delays are in hundredths of a second, so round the running total
+        // to keep long clips in sync
+        let mut elapsed = 0;
+        for (image, count) in &self.frames {
+            let start = elapsed * 100 / FRAMES_PER_SECOND;
+            elapsed += *count as u64;
+            let end = elapsed * 100 / FRAMES_PER_SECOND;
+
+            let frame = gif::Frame {
+                width: width as u16,
+                height: height as u16,
+                delay: (end - start).min(u16::MAX as u64) as u16,
+                buffer: Cow::Owned(self.scaled(image)),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_apng<W: Write>(&self, writer: W) -> Result<(), String> {
+        self.check_scale()?;
+
+        let (width, height) = (WIDTH * self.scale, HEIGHT * self.scale);
+        let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(self.palette.to_bytes());
+        encoder.set_animated(self.frames.len().max(1) as u32, 0).map_err(|e| e.to_string())?;
+
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        for (image, count) in &self.frames {
+            writer.set_frame_delay(*count, FRAMES_PER_SECOND as u16).map_err(|e| e.to_string())?;
+            writer.write_image_data(&self.scaled(image)).map_err(|e| e.to_string())?;
+        }
+
+        if self.frames.is_empty() {
+            writer.write_image_data(&self.scaled(&[0; WIDTH * HEIGHT])).map_err(|e| e.to_string())?;
+        }
+
+        writer.finish().map_err(|e| e.to_string())
+    }
+
+    fn check_scale(&self) -> Result<(), String> {
+        if self.scale > MAX_SCALE {
+            return Err(format!("clip scale {} is too large, at most {}", self.scale, MAX_SCALE));
+        }
+
+        Ok(())
+    }
+
+    fn scaled(&self, image: &[u8]) -> Vec<u8> {
+        scale_image(image, self.scale)
+    }
+}
+
+/// Palette indices for a display, every pixel blown up to scale x scale.
+pub(crate) fn scale_image(display: &[u8], scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(WIDTH * HEIGHT * scale * scale);
+
+    for row in display.chunks(WIDTH) {
+        for _ in 0..scale {
+            for pixel in row {
+                pixels.extend(std::iter::repeat_n((*pixel > 0) as u8, scale));
+            }
+        }
+    }
+
+    pixels
+}
+
+#[test]
+fn test_capture() {
+    let mut recorder = ClipRecorder::new(Palette::parse("33ff66,#000000").unwrap(), 2);
+    let mut display = [0; WIDTH * HEIGHT];
+
+    recorder.capture(&display);
+    recorder.capture(&display);
+    display[WIDTH + 1] = 1;
+    recorder.capture(&display);
+
+    assert_eq!(2, recorder.frames.len());
+    assert_eq!(3, recorder.length());
+
+    let scaled = recorder.scaled(&recorder.frames[1].0);
+    assert_eq!(4 * WIDTH * HEIGHT, scaled.len());
+    assert_eq!(vec!(0, 0, 1, 1, 0), scaled[2 * 2 * WIDTH..2 * 2 * WIDTH + 5].to_vec());
+
+    let mut gif = vec!();
+    recorder.write_gif(&mut gif).unwrap();
+    assert_eq!(b"GIF89a", &gif[..6]);
+    assert_eq!(&[0x33, 0xff, 0x66], &gif[16..19]);
+
+    let mut apng = vec!();
+    recorder.write_apng(&mut apng).unwrap();
+    assert_eq!(b"\x89PNG", &apng[..4]);
+    assert!(apng.windows(4).any(|chunk| chunk == b"acTL"));
+
+    assert_eq!(Ok(ClipFormat::Apng), ClipFormat::from_path("clip.PNG"));
+    assert!(Palette::parse("ffffff").is_err());
+}
+
+#[test]
+fn test_scale_limit() {
+    let mut recorder = ClipRecorder::new(Palette::default(), MAX_SCALE + 1);
+    recorder.capture(&[0; WIDTH * HEIGHT]);
+
+    assert!(recorder.write_gif(Vec::new()).is_err());
+    assert!(recorder.write_apng(Vec::new()).is_err());
+}
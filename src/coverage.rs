@@ -0,0 +1,135 @@
+use crate::chip8::Chip8;
+
+use serde_json::{json, Value};
+
+use std::fmt::Write as FmtWrite;
+
+const EXECUTED: u8 = 0b01;
+const DATA: u8 = 0b10;
+
+/// Per-byte record of which memory was executed as code and which was read as data.
+#[derive(Clone, Debug)]
+pub struct Coverage {
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn new(memory_size: usize) -> Self {
+        Coverage { flags: vec![0; memory_size] }
+    }
+
+    pub(crate) fn mark_executed(&mut self, addr: u16) {
+        self.flags[addr as usize] |= EXECUTED;
+        self.flags[addr as usize + 1] |= EXECUTED;
+    }
+
+    pub(crate) fn mark_data(&mut self, addr: u16) {
+        self.flags[addr as usize] |= DATA;
+    }
+
+    pub fn is_executed(&self, addr: u16) -> bool {
+        self.flag(addr) & EXECUTED != 0
+    }
+
+    pub fn is_data(&self, addr: u16) -> bool {
+        self.flag(addr) & DATA != 0
+    }
+
+    fn flag(&self, addr: u16) -> u8 {
+        self.flags.get(addr as usize).copied().unwrap_or_default()
+    }
+
+    fn ranges(&self, start: u16, end: u16, flag: u8) -> Vec<Value> {
+        let mut ranges = vec!();
+        let mut range_start = None;
+
+        for addr in start..=end {
+            let covered = addr < end && self.flag(addr) & flag != 0;
+
+            match (covered, range_start) {
+                (true, None) => range_start = Some(addr),
+                (false, Some(first)) => {
+                    ranges.push(json!({ "start": first, "end": addr - 1 }));
+                    range_start = None;
+                },
+                _ => {},
+            }
+        }
+
+        ranges
+    }
+
+    /// Coverage of the ROM image between `start` (inclusive) and `end` (exclusive) as JSON.
+    pub fn to_json(&self, start: u16, end: u16) -> Value {
+        let count = |flag: u8| (start..end).filter(|addr| self.flag(*addr) & flag != 0).count();
+        let covered = (start..end).filter(|addr| self.flag(*addr) != 0).count();
+        let size = end.saturating_sub(start) as usize;
+
+        json!({
+            "start": start,
+            "end": end,
+            "executed": self.ranges(start, end, EXECUTED),
+            "data": self.ranges(start, end, DATA),
+            "summary": {
+                "bytes": size,
+                "executed_bytes": count(EXECUTED),
+                "data_bytes": count(DATA),
+                "covered_percent": if size > 0 { covered as f64 * 100.0 / size as f64 } else { 0.0 },
+            },
+        })
+    }
+
+    /// Disassembly of the ROM image, with executed bytes shown as instructions
+    /// and bytes read as data shown as `DB`. Uncovered bytes are disassembled
+    /// but marked with a `.`.
+    pub fn listing(&self, chip8: &Chip8, start: u16, end: u16) -> String {
+        let mut listing = String::new();
+        let mut addr = start;
+
+        while addr < end {
+            let byte = chip8.memory[addr as usize];
+
+            if self.is_executed(addr) || (!self.is_data(addr) && addr + 1 < end) {
+                let marker = if self.is_executed(addr) { 'X' } else { '.' };
+                writeln!(listing, "{:04X}  {:04X}  {}  {}", addr, chip8.read_word(addr), marker, chip8.read_opcode_at(addr)).unwrap();
+                addr += 2;
+            } else {
+                let marker = if self.is_data(addr) { 'D' } else { '.' };
+                writeln!(listing, "{:04X}  {:02X}    {}  DB 0x{:02X}", addr, byte, marker, byte).unwrap();
+                addr += 1;
+            }
+        }
+
+        listing
+    }
+}
+
+#[test]
+fn test_coverage() {
+    // LD I, 0x206; DRW V0, V0, 2; JP 0x204; sprite 0xF0 0x90; unreachable LD V0, 0x01
+    let mut chip8 = Chip8::new_program(vec!(0xA2, 0x06, 0xD0, 0x02, 0x12, 0x04, 0xF0, 0x90, 0x60, 0x01));
+    chip8.enable_coverage();
+
+    for _ in 0..4 {
+        chip8.step(&std::collections::HashMap::new(), None).unwrap();
+    }
+
+    let coverage = chip8.coverage().unwrap();
+    assert!(coverage.is_executed(0x200) && coverage.is_executed(0x205));
+    assert!(!coverage.is_executed(0x206) && coverage.is_data(0x206) && coverage.is_data(0x207));
+    assert!(!coverage.is_executed(0x208) && !coverage.is_data(0x208));
+
+    let json = coverage.to_json(0x200, 0x20A);
+    assert_eq!(json!([{ "start": 0x200, "end": 0x205 }]), json["executed"]);
+    assert_eq!(json!([{ "start": 0x206, "end": 0x207 }]), json["data"]);
+    assert_eq!(6, json["summary"]["executed_bytes"]);
+
+    assert_eq!(
+        "0200  A206  X  LD I, 0x206\n\
+         0202  D002  X  DRW V0, V0, 2\n\
+         0204  1204  X  JP 0x204\n\
+         0206  F0    D  DB 0xF0\n\
+         0207  90    D  DB 0x90\n\
+         0208  6001  .  LD V0, 0x01\n",
+        coverage.listing(&chip8, 0x200, 0x20A));
+}
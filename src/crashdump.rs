@@ -0,0 +1,74 @@
+use crate::chip8::Chip8;
+use crate::instruction::{Instruction, OpCode};
+
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn format_crash_dump(chip8: &Chip8, reason: &str) -> String {
+    let mut dump = String::new();
+
+    writeln!(dump, "chip8 crash dump").unwrap();
+    writeln!(dump, "reason: {}", reason).unwrap();
+
+    writeln!(dump, "\nhistory (oldest first):").unwrap();
+    for (pc, word) in chip8.history() {
+        let opcode: OpCode = Instruction::from(*word).into();
+        writeln!(dump, "  {:04X} {:04X} {}", pc, word, opcode).unwrap();
+    }
+
+    writeln!(dump, "\nregisters:").unwrap();
+    let registers: Vec<String> = chip8.v.iter().enumerate().map(|(x, v)| format!("V{:X}={:02X}", x, v)).collect();
+    writeln!(dump, "  {}", registers[..8].join(" ")).unwrap();
+    writeln!(dump, "  {}", registers[8..].join(" ")).unwrap();
+    writeln!(dump, "  I={:04X} PC={:04X} SP={:02X} DT={:02X} ST={:02X}",
+        chip8.i, chip8.pc, chip8.stack.len(), chip8.delay_timer, chip8.sound_timer).unwrap();
+    if let Some(vx) = chip8.waiting_for_input_vx {
+        writeln!(dump, "  waiting for input into V{:X}", vx).unwrap();
+    }
+
+    writeln!(dump, "\nstack:").unwrap();
+    for (depth, addr) in chip8.stack.iter().enumerate() {
+        writeln!(dump, "  [{:X}] {:04X}", depth, addr).unwrap();
+    }
+
+    writeln!(dump, "\nmemory:").unwrap();
+    for (row, bytes) in chip8.memory.chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        writeln!(dump, "  {:04X}: {}", row * 16, hex.join(" ")).unwrap();
+    }
+
+    writeln!(dump, "\ndisplay:").unwrap();
+    for row in chip8.display.chunks(64) {
+        let pixels: String = row.iter().map(|pixel| if *pixel > 0 { '#' } else { '.' }).collect();
+        writeln!(dump, "  {}", pixels).unwrap();
+    }
+
+    dump
+}
+
+/// Writes a crash dump to the temporary directory and returns its path.
+pub fn write_crash_dump(chip8: &Chip8, reason: &str) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_millis()).unwrap_or_default();
+    let path = std::env::temp_dir().join(format!("chip8-crash-{}.txt", timestamp));
+
+    std::fs::write(&path, format_crash_dump(chip8, reason))?;
+
+    Ok(path)
+}
+
+#[test]
+fn test_crash_dump() {
+    // LD V0, 0x05; CALL 0x206; NOOP; RET
+    let mut chip8 = Chip8::new_program(vec!(0x60, 0x05, 0x22, 0x06, 0x00, 0x00, 0x00, 0xEE));
+    let keyboard = std::collections::HashMap::new();
+
+    let fault = (0..10).map(|_| chip8.step(&keyboard, None)).find_map(|result| result.err()).unwrap();
+    let dump = format_crash_dump(&chip8, &fault.to_string());
+
+    assert!(dump.contains("reason: stack underflow"));
+    assert!(dump.contains("  0206 00EE RET\n  0204 0000 NOOP\n  0206 00EE RET\n"));
+    assert!(dump.contains("V0=05"));
+    assert!(dump.contains("  0200: 60 05 22 06 00 00 00 EE 00 00 00 00 00 00 00 00\n"));
+}
@@ -0,0 +1,586 @@
+use crate::chip8::{Chip8, Fault};
+use crate::opcode::OpCode;
+use crate::quirks::{Platform, Quirks};
+
+use serde_json::{json, Value};
+
+use std::collections::{HashMap, HashSet};
+use std::io::prelude::*;
+use std::io;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+const STACK_REFERENCE: u64 = 2;
+const MEMORY_REFERENCE: u64 = 3;
+const STEPS_PER_POLL: u32 = 1000;
+const INPUT_WAIT: Duration = Duration::from_millis(10);
+
+enum Run {
+    Stopped,
+    Continue,
+    StepOut(usize),
+    /// Runs until a CALL made at the given stack depth has returned.
+    StepOver(usize),
+}
+
+pub struct DapServer<W: Write> {
+    output: W,
+    seq: u64,
+    chip8: Option<Chip8>,
+    keyboard: HashMap<u8, bool>,
+    breakpoints: HashSet<u16>,
+    stop_on_entry: bool,
+    run: Run,
+    done: bool,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(output: W) -> Self {
+        let keyboard = (0..16).map(|key| (key, false)).collect();
+
+        DapServer {
+            output,
+            seq: 1,
+            chip8: None,
+            keyboard,
+            breakpoints: HashSet::new(),
+            stop_on_entry: false,
+            run: Run::Stopped,
+            done: false,
+        }
+    }
+
+    pub fn serve<R: BufRead + Send + 'static>(&mut self, input: R) -> io::Result<()> {
+        let requests = spawn_reader(input);
+
+        while !self.done {
+            let request = match self.run {
+                Run::Stopped => match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => break,
+                },
+                _ => {
+                    self.run_batch()?;
+                    match requests.try_recv() {
+                        Ok(request) => Some(request),
+                        Err(TryRecvError::Empty) => None,
+                        Err(TryRecvError::Disconnected) => break,
+                    }
+                },
+            };
+
+            if let Some(request) = request {
+                self.handle_request(&request)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_batch(&mut self) -> io::Result<()> {
+        let chip8 = match self.chip8.as_mut() {
+            Some(chip8) => chip8,
+            None => return Ok(()),
+        };
+
+        for _ in 0..STEPS_PER_POLL {
+            match chip8.step(&self.keyboard, None) {
+                // Nothing can press a key here, so give the client a chance to pause instead of spinning
+                Ok(state) if state.waiting_for_input => {
+                    thread::sleep(INPUT_WAIT);
+                    return Ok(());
+                },
+                Ok(_) => {},
+                Err(fault) => {
+                    self.run = Run::Stopped;
+                    return self.exception(fault);
+                },
+            }
+
+            let stopped = match self.run {
+                Run::StepOut(depth) => chip8.stack.len() < depth,
+                Run::StepOver(depth) => chip8.stack.len() <= depth,
+                _ => false,
+            };
+
+            if self.breakpoints.contains(&chip8.pc) {
+                self.run = Run::Stopped;
+                return self.stopped("breakpoint");
+            }
+
+            if stopped {
+                self.run = Run::Stopped;
+                return self.stopped("step");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn handle_request(&mut self, request: &Value) -> io::Result<()> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+
+        match command {
+            "initialize" => {
+                self.respond(request, json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                }))?;
+                self.event("initialized", json!({}))
+            },
+            "launch" => {
+                match launch(arguments) {
+                    Ok(chip8) => {
+                        self.chip8 = Some(chip8);
+                        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                        self.respond(request, json!({}))
+                    },
+                    Err(e) => self.fail(request, &e),
+                }
+            },
+            "setBreakpoints" => {
+                let breakpoints: Vec<Value> = arguments["breakpoints"].as_array().map_or(vec!(), |lines| {
+                    lines.iter().map(|_| json!({ "verified": false, "message": "no source map available" })).collect()
+                });
+                self.respond(request, json!({ "breakpoints": breakpoints }))
+            },
+            "setInstructionBreakpoints" => {
+                self.breakpoints.clear();
+
+                let mut breakpoints = vec!();
+                for breakpoint in arguments["breakpoints"].as_array().unwrap_or(&vec!()) {
+                    let addr = breakpoint["instructionReference"].as_str().and_then(parse_address)
+                        .map(|addr| addr.wrapping_add(breakpoint["offset"].as_i64().unwrap_or(0) as u16));
+
+                    if let Some(addr) = addr {
+                        self.breakpoints.insert(addr);
+                        breakpoints.push(json!({ "verified": true, "instructionReference": format_address(addr) }));
+                    } else {
+                        breakpoints.push(json!({ "verified": false, "message": "invalid address" }));
+                    }
+                }
+                self.respond(request, json!({ "breakpoints": breakpoints }))
+            },
+            "configurationDone" => {
+                self.respond(request, json!({}))?;
+                if self.stop_on_entry {
+                    self.stopped("entry")
+                } else {
+                    self.run = Run::Continue;
+                    Ok(())
+                }
+            },
+            "threads" => self.respond(request, json!({ "threads": [{ "id": THREAD_ID, "name": "chip8" }] })),
+            "stackTrace" => {
+                let frames = self.stack_frames();
+                let total = frames.len();
+                self.respond(request, json!({ "stackFrames": frames, "totalFrames": total }))
+            },
+            "scopes" => self.respond(request, json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REFERENCE, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY_REFERENCE, "expensive": true },
+            ]})),
+            "variables" => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or_default();
+                let variables = self.variables(reference);
+                self.respond(request, json!({ "variables": variables }))
+            },
+            "continue" => {
+                self.run = Run::Continue;
+                self.respond(request, json!({ "allThreadsContinued": true }))
+            },
+            "next" => {
+                self.respond(request, json!({}))?;
+                // Past the end of memory there is no instruction to look at, and stepping faults
+                let call_depth = self.chip8.as_ref()
+                    .filter(|chip8| (chip8.pc as usize) + 1 < chip8.memory.len())
+                    .and_then(|chip8| match chip8.read_opcode() {
+                        OpCode::CALL { .. } => Some(chip8.stack.len()),
+                        _ => None,
+                    });
+
+                match call_depth {
+                    Some(depth) => {
+                        self.run = Run::StepOver(depth);
+                        Ok(())
+                    },
+                    None => self.step_instruction(),
+                }
+            },
+            "stepIn" => {
+                self.respond(request, json!({}))?;
+                self.step_instruction()
+            },
+            "stepOut" => {
+                self.respond(request, json!({}))?;
+                let depth = self.chip8.as_ref().map_or(0, |chip8| chip8.stack.len());
+                if depth == 0 {
+                    self.stopped("step")
+                } else {
+                    self.run = Run::StepOut(depth);
+                    Ok(())
+                }
+            },
+            "pause" => {
+                self.respond(request, json!({}))?;
+                self.run = Run::Stopped;
+                self.stopped("pause")
+            },
+            "disconnect" | "terminate" => {
+                self.done = true;
+                self.respond(request, json!({}))?;
+                self.event("terminated", json!({}))
+            },
+            _ => self.fail(request, &format!("unsupported request {}", command)),
+        }
+    }
+
+    fn step_instruction(&mut self) -> io::Result<()> {
+        if let Some(chip8) = self.chip8.as_mut() {
+            if let Err(fault) = chip8.step(&self.keyboard, None) {
+                return self.exception(fault);
+            }
+        }
+        self.stopped("step")
+    }
+
+    fn stack_frames(&self) -> Vec<Value> {
+        let chip8 = match self.chip8.as_ref() {
+            Some(chip8) => chip8,
+            None => return vec!(),
+        };
+
+        let mut addresses = vec!(chip8.pc);
+        addresses.extend(chip8.stack.iter().rev().map(|addr| addr.wrapping_add(2)));
+
+        // Only disassemble addresses that hold a whole instruction, PC can be anywhere
+        let name = |addr: u16| if (addr as usize) + 1 < chip8.memory.len() {
+            chip8.read_opcode_at(addr).to_string()
+        } else {
+            "??".to_string()
+        };
+
+        addresses.iter().enumerate().map(|(id, addr)| json!({
+            "id": id,
+            "name": name(*addr),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format_address(*addr),
+        })).collect()
+    }
+
+    fn variables(&self, reference: u64) -> Vec<Value> {
+        let chip8 = match self.chip8.as_ref() {
+            Some(chip8) => chip8,
+            None => return vec!(),
+        };
+
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+
+        match reference {
+            REGISTERS_REFERENCE => {
+                let mut variables: Vec<Value> = chip8.v.iter().enumerate()
+                    .map(|(x, value)| variable(format!("V{:X}", x), format!("0x{:02X}", value)))
+                    .collect();
+                variables.push(variable("I".to_string(), format_address(chip8.i)));
+                variables.push(variable("PC".to_string(), format_address(chip8.pc)));
+                variables.push(variable("SP".to_string(), chip8.stack.len().to_string()));
+                variables.push(variable("DT".to_string(), chip8.delay_timer.to_string()));
+                variables.push(variable("ST".to_string(), chip8.sound_timer.to_string()));
+                variables
+            },
+            STACK_REFERENCE => chip8.stack.iter().enumerate()
+                .map(|(depth, addr)| variable(format!("[{}]", depth), format_address(*addr)))
+                .collect(),
+            MEMORY_REFERENCE => chip8.memory.chunks(16).enumerate()
+                .map(|(row, bytes)| {
+                    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    variable(format_address((row * 16) as u16), hex.join(" "))
+                })
+                .collect(),
+            _ => vec!(),
+        }
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn stopped(&mut self, reason: &str) -> io::Result<()> {
+        self.event("stopped", json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }))
+    }
+
+    fn exception(&mut self, fault: Fault) -> io::Result<()> {
+        self.event("stopped", json!({
+            "reason": "exception",
+            "description": fault.to_string(),
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+
+        let content = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+        self.output.flush()
+    }
+}
+
+/// Builds the machine for a launch request. `platform` and `quirks` take the
+/// same values as the `--platform` and `--quirks` options.
+fn launch(arguments: &Value) -> Result<Chip8, String> {
+    let program = arguments["program"].as_str().unwrap_or_default();
+    let rom = std::fs::read(program).map_err(|e| format!("could not read {}: {}", program, e))?;
+
+    let platform = arguments["platform"].as_str().map_or(Ok(Platform::Chip8), Platform::parse)?;
+    let quirks = match arguments["quirks"].as_str() {
+        Some(list) => Quirks::parse(list)?,
+        None => platform.quirks(),
+    };
+
+    Chip8::builder().program(rom).quirks(quirks).build()
+}
+
+fn parse_address(reference: &str) -> Option<u16> {
+    let reference = reference.trim();
+    if let Some(hex) = reference.strip_prefix("0x").or_else(|| reference.strip_prefix("0X")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        reference.parse().ok()
+    }
+}
+
+fn format_address(addr: u16) -> String {
+    format!("0x{:04X}", addr)
+}
+
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        if let Some(length) = line.strip_prefix("Content-Length:") {
+            content_length = length.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut content = vec![0u8; content_length.unwrap_or_default()];
+    input.read_exact(&mut content)?;
+
+    serde_json::from_slice(&content).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn spawn_reader<R: BufRead + Send + 'static>(mut input: R) -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+#[cfg(test)]
+fn frame(message: Value) -> Vec<u8> {
+    let content = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", content.len(), content).into_bytes()
+}
+
+#[test]
+fn test_parse_address() {
+    assert_eq!(Some(0x200), parse_address("0x200"));
+    assert_eq!(Some(512), parse_address("512"));
+    assert_eq!(None, parse_address("zzz"));
+}
+
+#[test]
+fn test_launch_arguments() {
+    let path = std::env::temp_dir().join(format!("chip8-dap-launch-{}.ch8", std::process::id()));
+    std::fs::write(&path, vec![0x12; 8192]).unwrap();
+    let program = path.to_str().unwrap();
+
+    let chip8 = launch(&json!({ "program": program })).unwrap();
+    assert_eq!(Platform::Chip8.quirks(), chip8.quirks);
+    assert_eq!(0x12, chip8.memory[chip8.memory.len() - 1]);
+
+    let chip8 = launch(&json!({ "program": program, "platform": "vip" })).unwrap();
+    assert_eq!(Platform::CosmacVip.quirks(), chip8.quirks);
+
+    let chip8 = launch(&json!({ "program": program, "platform": "vip", "quirks": "clip" })).unwrap();
+    assert_eq!(Quirks { clip_sprites: true, ..Quirks::default() }, chip8.quirks);
+
+    assert!(launch(&json!({ "program": program, "platform": "nes" })).is_err());
+    assert!(launch(&json!({ "program": program, "quirks": "fast" })).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_session() {
+    // LD V0, 0x05; CALL 0x206; JP 0x204; ADD V0, 0x01; RET
+    let rom = vec!(0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0x70, 0x01, 0x00, 0xEE);
+    let path = std::env::temp_dir().join(format!("chip8-dap-test-{}.ch8", std::process::id()));
+    std::fs::write(&path, rom).unwrap();
+
+    let requests = vec!(
+        json!({ "seq": 1, "type": "request", "command": "initialize", "arguments": {} }),
+        json!({ "seq": 2, "type": "request", "command": "launch", "arguments": { "program": path.to_str().unwrap() } }),
+        json!({ "seq": 3, "type": "request", "command": "setInstructionBreakpoints",
+                "arguments": { "breakpoints": [{ "instructionReference": "0x206" }] } }),
+        json!({ "seq": 4, "type": "request", "command": "configurationDone" }),
+        json!({ "seq": 5, "type": "request", "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "seq": 6, "type": "request", "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "seq": 7, "type": "request", "command": "stepOut", "arguments": { "threadId": 1 } }),
+        json!({ "seq": 8, "type": "request", "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "seq": 9, "type": "request", "command": "disconnect" }),
+    );
+    let input: Vec<u8> = requests.into_iter().flat_map(frame).collect();
+
+    let mut output = vec!();
+    DapServer::new(&mut output).serve(io::Cursor::new(input)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut output = io::Cursor::new(output);
+    let mut messages = vec!();
+    while let Some(message) = read_message(&mut output).unwrap() {
+        messages.push(message);
+    }
+
+    let find = |request_seq: u64| messages.iter().find(|m| m["request_seq"] == request_seq).unwrap().clone();
+    let stops: Vec<&Value> = messages.iter().filter(|m| m["event"] == "stopped").collect();
+
+    assert_eq!("initialized", messages[1]["event"]);
+    assert!(find(2)["success"].as_bool().unwrap());
+    assert_eq!(true, find(3)["body"]["breakpoints"][0]["verified"]);
+
+    assert_eq!("breakpoint", stops[0]["body"]["reason"]);
+    let frames = &find(5)["body"]["stackFrames"];
+    assert_eq!("0x0206", frames[0]["instructionPointerReference"]);
+    assert_eq!("0x0204", frames[1]["instructionPointerReference"]);
+    assert_eq!("0x05", find(6)["body"]["variables"][0]["value"]);
+
+    assert_eq!("step", stops[1]["body"]["reason"]);
+    let registers = &find(8)["body"]["variables"];
+    assert_eq!("0x06", registers[0]["value"]);
+    assert_eq!("0x0204", registers[17]["value"]);
+
+    assert_eq!("terminated", messages.last().unwrap()["event"]);
+}
+
+#[test]
+fn test_step_over() {
+    // LD V0, 0x05; CALL 0x206; JP 0x204; ADD V0, 0x01; RET
+    let rom = vec!(0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0x70, 0x01, 0x00, 0xEE);
+    let path = std::env::temp_dir().join(format!("chip8-dap-step-test-{}.ch8", std::process::id()));
+    std::fs::write(&path, rom).unwrap();
+
+    let request = |seq: u64, command: &str| json!({ "seq": seq, "type": "request", "command": command, "arguments": { "threadId": 1 } });
+    let requests = vec!(
+        json!({ "seq": 1, "type": "request", "command": "launch", "arguments": { "program": path.to_str().unwrap(), "stopOnEntry": true } }),
+        request(2, "configurationDone"),
+        request(3, "next"),
+        request(4, "next"),
+        request(5, "stackTrace"),
+        request(6, "disconnect"),
+    );
+    let input: Vec<u8> = requests.into_iter().flat_map(frame).collect();
+
+    let mut output = vec!();
+    DapServer::new(&mut output).serve(io::Cursor::new(input)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut output = io::Cursor::new(output);
+    let mut messages = vec!();
+    while let Some(message) = read_message(&mut output).unwrap() {
+        messages.push(message);
+    }
+
+    // Stepping over the CALL stops after it returns, with the subroutine run
+    let stack_trace = messages.iter().find(|m| m["request_seq"] == 5).unwrap();
+    let frames = &stack_trace["body"]["stackFrames"];
+    assert_eq!(1, frames.as_array().unwrap().len());
+    assert_eq!("0x0204", frames[0]["instructionPointerReference"]);
+}
+
+#[test]
+fn test_stack_trace_after_fault() {
+    // CALL 0x204; 0x0000; JP 0xFFF
+    let rom = vec!(0x22, 0x04, 0x00, 0x00, 0x1F, 0xFF);
+    let path = std::env::temp_dir().join(format!("chip8-dap-fault-test-{}.ch8", std::process::id()));
+    std::fs::write(&path, rom).unwrap();
+
+    let request = |seq: u64, command: &str| json!({ "seq": seq, "type": "request", "command": command, "arguments": { "threadId": 1 } });
+    let requests = vec!(
+        json!({ "seq": 1, "type": "request", "command": "launch", "arguments": { "program": path.to_str().unwrap(), "stopOnEntry": true } }),
+        request(2, "configurationDone"),
+        request(3, "stepIn"),
+        request(4, "stepIn"),
+        request(5, "next"),
+        request(6, "stackTrace"),
+        request(7, "disconnect"),
+    );
+    let input: Vec<u8> = requests.into_iter().flat_map(frame).collect();
+
+    let mut output = vec!();
+    DapServer::new(&mut output).serve(io::Cursor::new(input)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut output = io::Cursor::new(output);
+    let mut messages = vec!();
+    while let Some(message) = read_message(&mut output).unwrap() {
+        messages.push(message);
+    }
+
+    let stops: Vec<&Value> = messages.iter().filter(|m| m["event"] == "stopped").collect();
+    assert_eq!("exception", stops.last().unwrap()["body"]["reason"]);
+
+    // The stack can still be inspected with PC past the end of memory
+    let stack_trace = messages.iter().find(|m| m["request_seq"] == 6).unwrap();
+    let frames = &stack_trace["body"]["stackFrames"];
+    assert_eq!("0x0FFF", frames[0]["instructionPointerReference"]);
+    assert_eq!("??", frames[0]["name"]);
+    assert_eq!("0x0202", frames[1]["instructionPointerReference"]);
+}
@@ -0,0 +1,112 @@
+//! The panels of the SDL debug layout, as text the frontend draws with the
+//! bitmap font next to the game view.
+
+use crate::chip8::Chip8;
+
+// Instructions shown before and after the one at PC
+const DISASSEMBLY_CONTEXT: u16 = 5;
+const MEMORY_ROWS: usize = 4;
+const MEMORY_ROW_LENGTH: usize = 8;
+/// The tallest sprite DRW can draw.
+pub const SPRITE_ROWS: usize = 15;
+
+#[derive(Debug, Default, Clone)]
+pub struct DebugPanels {
+    pub disassembly: Vec<String>,
+    pub registers: Vec<String>,
+    pub stack: Vec<String>,
+    pub memory: Vec<String>,
+    /// The bytes at I, one sprite row each.
+    pub sprite: Vec<u8>,
+    pub sprite_address: u16,
+}
+
+impl DebugPanels {
+    pub fn capture(chip8: &Chip8) -> Self {
+        DebugPanels {
+            disassembly: disassembly(chip8),
+            registers: registers(chip8),
+            stack: stack(chip8),
+            memory: memory(chip8),
+            sprite: chip8.memory().iter().skip(chip8.i() as usize).take(SPRITE_ROWS).cloned().collect(),
+            sprite_address: chip8.i(),
+        }
+    }
+}
+
+fn disassembly(chip8: &Chip8) -> Vec<String> {
+    let pc = chip8.pc();
+    let first = pc.saturating_sub(DISASSEMBLY_CONTEXT * 2);
+    let mut lines = vec!("DISASSEMBLY".to_string());
+
+    for addr in (first..=pc.saturating_add(DISASSEMBLY_CONTEXT * 2)).step_by(2) {
+        if addr as usize + 1 >= chip8.memory().len() {
+            break;
+        }
+
+        let marker = if addr == pc { ">" } else { " " };
+        lines.push(format!("{}{:04X} {:04X} {}", marker, addr, chip8.read_word(addr), chip8.read_opcode_at(addr)));
+    }
+
+    lines
+}
+
+fn registers(chip8: &Chip8) -> Vec<String> {
+    let mut lines = vec!("REGISTERS".to_string());
+
+    for (name, registers) in ["V0-7", "V8-F"].iter().zip(chip8.registers().chunks(8)) {
+        let values: Vec<String> = registers.iter().map(|value| format!("{:02X}", value)).collect();
+        lines.push(format!("{} {}", name, values.join(" ")));
+    }
+
+    let (delay, sound) = chip8.timers();
+    lines.push(format!("I {:04X}  PC {:04X}", chip8.i(), chip8.pc()));
+    lines.push(format!("DT {:02X}  ST {:02X}", delay, sound));
+
+    lines
+}
+
+fn stack(chip8: &Chip8) -> Vec<String> {
+    let stack = chip8.stack();
+    let mut lines = vec!(format!("STACK {}", stack.len()));
+
+    if stack.is_empty() {
+        lines.push("-".to_string());
+    }
+
+    for entries in stack.chunks(4) {
+        let addresses: Vec<String> = entries.iter().map(|addr| format!("{:04X}", addr)).collect();
+        lines.push(addresses.join(" "));
+    }
+
+    lines
+}
+
+fn memory(chip8: &Chip8) -> Vec<String> {
+    let start = chip8.i() as usize & !(MEMORY_ROW_LENGTH - 1);
+    let mut lines = vec!(format!("MEMORY AT I {:04X}", chip8.i()));
+
+    for (row, bytes) in chip8.memory()[start.min(chip8.memory().len())..].chunks(MEMORY_ROW_LENGTH).take(MEMORY_ROWS).enumerate() {
+        let values: Vec<String> = bytes.iter().map(|value| format!("{:02X}", value)).collect();
+        lines.push(format!("{:04X} {}", start + row * MEMORY_ROW_LENGTH, values.join(" ")));
+    }
+
+    lines
+}
+
+#[test]
+fn test_capture() {
+    let mut chip8 = Chip8::new_program(vec!(0x60, 0x12, 0x22, 0x08, 0x00, 0x00, 0x00, 0x00, 0xA2, 0x0A, 0xF0, 0x90));
+    for _ in 0..3 {
+        chip8.step(&Default::default(), None).unwrap();
+    }
+
+    let panels = DebugPanels::capture(&chip8);
+    assert_eq!(" 0208 A20A LD I, 0x20A", panels.disassembly[5]);
+    assert!(panels.disassembly[6].starts_with(">020A F090"));
+    assert_eq!("V0-7 12 00 00 00 00 00 00 00", panels.registers[1]);
+    assert_eq!(vec!("STACK 1", "0202"), panels.stack);
+    assert_eq!("MEMORY AT I 020A", panels.memory[0]);
+    assert_eq!("0208 A2 0A F0 90 00 00 00 00", panels.memory[1]);
+    assert_eq!(vec!(0xF0, 0x90, 0, 0, 0), panels.sprite[..5].to_vec());
+}
@@ -0,0 +1,481 @@
+use crate::chip8::Chip8;
+
+use std::collections::{HashMap, HashSet};
+use std::io::prelude::*;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// Register numbering as exposed to the debugger: V0-VF, then I, PC, SP, DT and ST.
+// Multi-byte registers are sent big-endian, like the CHIP-8 itself.
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+const REG_COUNT: usize = 21;
+
+const SIGTRAP: &str = "S05";
+const SIGSEGV: &str = "S0B";
+const INTERRUPT: u8 = 0x03;
+// How long a continued program blocked on LD Vx, K sleeps between interrupt checks
+const INPUT_WAIT: Duration = Duration::from_millis(10);
+
+enum Resume {
+    Continue,
+    Step,
+}
+
+pub struct GdbStub {
+    chip8: Chip8,
+    breakpoints: HashSet<u16>,
+    keyboard: HashMap<u8, bool>,
+}
+
+impl GdbStub {
+    pub fn new(chip8: Chip8) -> Self {
+        let keyboard = (0..16).map(|key| (key, false)).collect();
+
+        GdbStub { chip8, breakpoints: HashSet::new(), keyboard }
+    }
+
+    pub fn listen(&mut self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("gdb stub listening on {}", listener.local_addr()?);
+
+        let (stream, addr) = listener.accept()?;
+        println!("gdb connected from {}", addr);
+
+        self.serve(stream)
+    }
+
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+
+        while let Some(packet) = read_packet(&mut stream)? {
+            let reply = match packet.chars().next() {
+                Some('c') => self.resume(&packet[1..], Resume::Continue, &mut stream)?,
+                Some('s') => self.resume(&packet[1..], Resume::Step, &mut stream)?,
+                Some('k') => return Ok(()),
+                Some('D') => {
+                    write_packet(&mut stream, "OK")?;
+                    return Ok(());
+                },
+                _ => self.handle_packet(&packet),
+            };
+
+            write_packet(&mut stream, &reply)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn handle_packet(&mut self, packet: &str) -> String {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+
+        match command {
+            "?" => SIGTRAP.to_string(),
+            "g" => (0..REG_COUNT).map(|reg| self.read_register(reg)).collect(),
+            "G" => self.write_registers(args),
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(reg) if reg < REG_COUNT => self.read_register(reg),
+                _ => "E01".to_string(),
+            },
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" => self.breakpoint(args, true),
+            "z" => self.breakpoint(args, false),
+            "H" => "OK".to_string(),
+            "q" if args.starts_with("Supported") => "PacketSize=1000".to_string(),
+            "q" if args == "Attached" => "1".to_string(),
+            "q" if args == "C" => "QC1".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn resume(&mut self, args: &str, resume: Resume, stream: &mut TcpStream) -> io::Result<String> {
+        if let Ok(addr) = u16::from_str_radix(args, 16) {
+            self.chip8.pc = addr;
+        }
+
+        if self.chip8.step(&self.keyboard, None).is_err() {
+            return Ok(SIGSEGV.to_string());
+        }
+
+        if let Resume::Continue = resume {
+            let mut steps: u32 = 0;
+
+            while !self.breakpoints.contains(&self.chip8.pc) {
+                steps = steps.wrapping_add(1);
+                if steps.is_multiple_of(1024) && interrupted(stream)? {
+                    break;
+                }
+
+                match self.chip8.step(&self.keyboard, None) {
+                    // Nothing can press a key here, so wait for the debugger instead of spinning
+                    Ok(state) if state.waiting_for_input => {
+                        if interrupted(stream)? {
+                            break;
+                        }
+                        thread::sleep(INPUT_WAIT);
+                    },
+                    Ok(_) => {},
+                    Err(_) => return Ok(SIGSEGV.to_string()),
+                }
+            }
+        }
+
+        Ok(SIGTRAP.to_string())
+    }
+
+    fn read_register(&self, reg: usize) -> String {
+        match reg {
+            0..=15 => format!("{:02x}", self.chip8.v[reg]),
+            REG_I => format!("{:04x}", self.chip8.i),
+            REG_PC => format!("{:04x}", self.chip8.pc),
+            REG_SP => format!("{:02x}", self.chip8.stack.len()),
+            REG_DT => format!("{:02x}", self.chip8.delay_timer),
+            REG_ST => format!("{:02x}", self.chip8.sound_timer),
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_register(&mut self, reg: usize, value: u16) -> bool {
+        match reg {
+            0..=15 => self.chip8.v[reg] = value as u8,
+            REG_I => self.chip8.i = value,
+            REG_PC => self.chip8.pc = value,
+            REG_SP if value as usize > self.chip8.stack_depth => return false,
+            REG_SP => self.chip8.stack.resize(value as usize, 0),
+            REG_DT => self.chip8.delay_timer = value as u8,
+            REG_ST => self.chip8.sound_timer = value as u8,
+            _ => unreachable!(),
+        }
+
+        true
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let mut offset = 0;
+
+        for reg in 0..REG_COUNT {
+            let width = register_width(reg);
+            match args.get(offset..offset + width).and_then(|hex| u16::from_str_radix(hex, 16).ok()) {
+                Some(value) if self.set_register(reg, value) => {},
+                _ => return "E01".to_string(),
+            }
+            offset += width;
+        }
+
+        "OK".to_string()
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let mut parts = args.splitn(2, '=');
+        let reg = parts.next().and_then(|reg| usize::from_str_radix(reg, 16).ok());
+        let value = parts.next().and_then(|value| u16::from_str_radix(value, 16).ok());
+
+        match (reg, value) {
+            (Some(reg), Some(value)) if reg < REG_COUNT && self.set_register(reg, value) => "OK".to_string(),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        match self.memory_range(args) {
+            Some((start, end)) => self.chip8.memory[start..end].iter().map(|byte| format!("{:02x}", byte)).collect(),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let mut parts = args.splitn(2, ':');
+        let range = parts.next().and_then(|range| self.memory_range(range));
+        let data = parts.next().and_then(decode_hex);
+
+        match (range, data) {
+            (Some((start, end)), Some(data)) if data.len() == end - start => {
+                self.chip8.memory[start..end].copy_from_slice(&data);
+                "OK".to_string()
+            },
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn memory_range(&self, args: &str) -> Option<(usize, usize)> {
+        let mut parts = args.splitn(2, ',');
+        let start = usize::from_str_radix(parts.next()?, 16).ok()?;
+        let length = usize::from_str_radix(parts.next()?, 16).ok()?;
+
+        let end = start.checked_add(length).filter(|&end| end <= self.chip8.memory.len())?;
+        Some((start, end))
+    }
+
+    fn breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let mut parts = args.split(',');
+        let kind = parts.next();
+        let addr = parts.next().and_then(|addr| u16::from_str_radix(addr, 16).ok());
+
+        match (kind, addr) {
+            (Some("0"), Some(addr)) | (Some("1"), Some(addr)) => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                "OK".to_string()
+            },
+            _ => String::new(),
+        }
+    }
+}
+
+fn register_width(reg: usize) -> usize {
+    match reg {
+        REG_I | REG_PC => 4,
+        _ => 2,
+    }
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Reads the next packet and acknowledges it, asking for a retransmission
+/// while the checksum does not match.
+fn read_packet(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    let mut byte = [0u8];
+
+    loop {
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+
+        let mut data = vec!();
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            if byte[0] == b'#' {
+                break;
+            }
+
+            data.push(byte[0]);
+        }
+
+        let mut sum = [0u8; 2];
+        stream.read_exact(&mut sum)?;
+
+        let data = String::from_utf8_lossy(&data).into_owned();
+        let expected = std::str::from_utf8(&sum).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok());
+
+        if expected == Some(checksum(&data)) {
+            stream.write_all(b"+")?;
+            return Ok(Some(data));
+        }
+
+        stream.write_all(b"-")?;
+    }
+}
+
+fn write_packet(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+    write!(stream, "${}#{:02x}", data, checksum(data))?;
+    stream.flush()
+}
+
+fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+    let mut byte = [0u8];
+
+    stream.set_nonblocking(true)?;
+    let result = loop {
+        match stream.peek(&mut byte) {
+            // Acknowledgements of earlier replies can sit in front of the interrupt
+            Ok(1) if byte[0] == b'+' || byte[0] == b'-' => {
+                if let Err(e) = stream.read_exact(&mut byte) {
+                    break Err(e);
+                }
+            },
+            Ok(1) if byte[0] == INTERRUPT => break stream.read_exact(&mut byte).map(|_| true),
+            Ok(_) => break Ok(false),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(false),
+            Err(e) => break Err(e),
+        }
+    };
+    stream.set_nonblocking(false)?;
+
+    result
+}
+
+#[cfg(test)]
+fn test_program() -> Chip8 {
+    // LD V0, 0x05; ADD V0, 0x01; JP 0x202
+    Chip8::new_program(vec!(0x60, 0x05, 0x70, 0x01, 0x12, 0x02))
+}
+
+#[test]
+fn test_checksum() {
+    assert_eq!(0x00, checksum(""));
+    assert_eq!(0x9a, checksum("OK"));
+}
+
+#[test]
+fn test_registers() {
+    let mut stub = GdbStub::new(test_program());
+    stub.chip8.v[0xA] = 0x42;
+    stub.chip8.i = 0x0123;
+
+    let registers = stub.handle_packet("g");
+    assert_eq!(2 * 16 + 4 + 4 + 2 + 2 + 2, registers.len());
+    assert_eq!("42", &registers[20..22]);
+    assert_eq!("0123", &registers[32..36]);
+    assert_eq!("0200", &registers[36..40]);
+
+    assert_eq!("OK", stub.handle_packet("P11=0300"));
+    assert_eq!("0300", stub.handle_packet("p11"));
+    assert_eq!("E01", stub.handle_packet("p15"));
+
+    assert_eq!("OK", stub.handle_packet("P12=02"));
+    assert_eq!("02", stub.handle_packet("p12"));
+    assert_eq!("E01", stub.handle_packet("P12=ff"));
+    assert_eq!("02", stub.handle_packet("p12"));
+}
+
+#[test]
+fn test_memory() {
+    let mut stub = GdbStub::new(test_program());
+
+    assert_eq!("60057001", stub.handle_packet("m200,4"));
+    assert_eq!("OK", stub.handle_packet("M204,2:00e0"));
+    assert_eq!("00e0", stub.handle_packet("m204,2"));
+    assert_eq!("E01", stub.handle_packet("mfff,2"));
+    assert_eq!("E01", stub.handle_packet("mffffffffffffffff,2"));
+    assert_eq!("E01", stub.handle_packet("Mffffffffffffffff,2:0000"));
+}
+
+#[test]
+fn test_fault_stops_with_sigsegv() {
+    // RET with an empty stack
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(Chip8::new_program(vec!(0x00, 0xEE))).serve(stream).unwrap();
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    write_packet(&mut client, "c").unwrap();
+
+    let mut ack = [0u8];
+    client.read_exact(&mut ack).unwrap();
+    assert_eq!(SIGSEGV, read_packet(&mut client).unwrap().unwrap());
+
+    write_packet(&mut client, "k").unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn test_bad_checksum_is_nacked() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(test_program()).serve(stream).unwrap();
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    let mut ack = [0u8];
+
+    client.write_all(b"$p11#00").unwrap();
+    client.read_exact(&mut ack).unwrap();
+    assert_eq!(b'-', ack[0]);
+
+    write_packet(&mut client, "p11").unwrap();
+    client.read_exact(&mut ack).unwrap();
+    assert_eq!(b'+', ack[0]);
+    assert_eq!("0200", read_packet(&mut client).unwrap().unwrap());
+
+    write_packet(&mut client, "k").unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn test_interrupt_while_waiting_for_input() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        // LD V0, K
+        GdbStub::new(Chip8::new_program(vec!(0xF0, 0x0A))).serve(stream).unwrap();
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    let mut ack = [0u8];
+
+    write_packet(&mut client, "c").unwrap();
+    client.read_exact(&mut ack).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    client.write_all(&[INTERRUPT]).unwrap();
+    assert_eq!(SIGTRAP, read_packet(&mut client).unwrap().unwrap());
+
+    write_packet(&mut client, "k").unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn test_scripted_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(test_program()).serve(stream).unwrap();
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    let mut request = |packet: &str| -> String {
+        write_packet(&mut client, packet).unwrap();
+
+        let mut ack = [0u8];
+        client.read_exact(&mut ack).unwrap();
+        assert_eq!(b'+', ack[0]);
+
+        read_packet(&mut client).unwrap().unwrap()
+    };
+
+    assert_eq!("OK", request("Z0,202,2"));
+    assert_eq!(SIGTRAP, request("c"));
+    assert_eq!("0202", request("p11"));
+    assert_eq!("05", request("p0"));
+
+    assert_eq!(SIGTRAP, request("s"));
+    assert_eq!("0204", request("p11"));
+    assert_eq!("06", request("p0"));
+
+    assert_eq!(SIGTRAP, request("c"));
+    assert_eq!("0202", request("p11"));
+
+    assert_eq!("OK", request("z0,202,2"));
+    assert_eq!("OK", request("D"));
+
+    server.join().unwrap();
+}
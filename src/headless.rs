@@ -0,0 +1,43 @@
+use crate::{ApplicationState, KeyboardHandler, StateHandler};
+
+use std::collections::HashMap;
+
+pub struct HeadlessEngine {
+    keyboard: HashMap<u8, bool>,
+    cycles: Option<u64>,
+    executed: u64,
+    display: [u8; 64*32],
+}
+
+impl HeadlessEngine {
+    pub fn new(cycles: Option<u64>) -> Self {
+        let keyboard = (0..16).map(|key| (key, false)).collect();
+
+        HeadlessEngine { keyboard, cycles, executed: 0, display: [0; 64*32] }
+    }
+
+    pub fn display(&self) -> &[u8; 64*32] {
+        &self.display
+    }
+}
+
+impl StateHandler for HeadlessEngine {
+    fn handle_state(&mut self, state: crate::chip8::State) {
+        self.executed += 1;
+
+        if state.update_display {
+            self.display = state.display;
+        }
+    }
+}
+
+impl KeyboardHandler for HeadlessEngine {
+    fn handle_keyboard(&mut self) -> (&HashMap<u8, bool>, Option<u8>, ApplicationState) {
+        let application_state = match self.cycles {
+            Some(cycles) if self.executed >= cycles => ApplicationState::Stopping,
+            _ => ApplicationState::Running,
+        };
+
+        (&self.keyboard, None, application_state)
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::Write;
+
+pub const WIDTH: usize = 64;
+
+/// Read, write and execute counts for every byte of memory.
+#[derive(Clone, Debug)]
+pub struct Heatmap {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    executes: Vec<u64>,
+}
+
+impl Heatmap {
+    pub fn new(memory_size: usize) -> Self {
+        Heatmap { reads: vec![0; memory_size], writes: vec![0; memory_size], executes: vec![0; memory_size] }
+    }
+
+    pub(crate) fn record_read(&mut self, addr: u16) {
+        self.reads[addr as usize] += 1;
+    }
+
+    pub(crate) fn record_write(&mut self, addr: u16) {
+        self.writes[addr as usize] += 1;
+    }
+
+    pub(crate) fn record_execute(&mut self, addr: u16) {
+        self.executes[addr as usize] += 1;
+        self.executes[addr as usize + 1] += 1;
+    }
+
+    pub fn reads(&self, addr: u16) -> u64 {
+        self.reads[addr as usize]
+    }
+
+    pub fn writes(&self, addr: u16) -> u64 {
+        self.writes[addr as usize]
+    }
+
+    pub fn executes(&self, addr: u16) -> u64 {
+        self.executes[addr as usize]
+    }
+
+    pub fn height(&self) -> usize {
+        self.reads.len().div_ceil(WIDTH)
+    }
+
+    /// One RGB pixel per byte, 64 bytes per row: red for writes, green for
+    /// reads and blue for executes. Counts are scaled logarithmically so that
+    /// a single access is still visible next to a hot loop.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let mut pixels = vec![0; WIDTH * self.height() * 3];
+
+        for (channel, counts) in [&self.writes, &self.reads, &self.executes].iter().enumerate() {
+            let max = counts.iter().max().copied().unwrap_or_default();
+
+            for (addr, count) in counts.iter().enumerate() {
+                pixels[addr * 3 + channel] = intensity(*count, max);
+            }
+        }
+
+        pixels
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, WIDTH as u32, self.height() as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb())
+    }
+}
+
+fn intensity(count: u64, max: u64) -> u8 {
+    match (count, max) {
+        (0, _) => 0,
+        (_, 1) => 255,
+        _ => (64.0 + 191.0 * (count as f64).ln() / (max as f64).ln()) as u8,
+    }
+}
+
+#[test]
+fn test_intensity() {
+    assert_eq!(0, intensity(0, 100));
+    assert_eq!(64, intensity(1, 100));
+    assert_eq!(255, intensity(100, 100));
+    assert_eq!(255, intensity(1, 1));
+}
+
+#[test]
+fn test_heatmap() {
+    use crate::chip8::Chip8;
+
+    // LD I, 0x300; LD B, V0; LD V1, [I]; JP 0x206
+    let mut chip8 = Chip8::new_program(vec!(0xA3, 0x00, 0xF0, 0x33, 0xF1, 0x65, 0x12, 0x06));
+    chip8.enable_heatmap();
+
+    for _ in 0..6 {
+        chip8.step(&std::collections::HashMap::new(), None).unwrap();
+    }
+
+    let heatmap = chip8.heatmap().unwrap();
+    assert_eq!(1, heatmap.executes(0x200));
+    assert_eq!(3, heatmap.executes(0x207));
+    assert_eq!((1, 1), (heatmap.writes(0x300), heatmap.writes(0x302)));
+    assert_eq!((1, 1, 0), (heatmap.reads(0x300), heatmap.reads(0x301), heatmap.reads(0x302)));
+
+    let pixels = heatmap.to_rgb();
+    assert_eq!(64 * 64 * 3, pixels.len());
+    assert_eq!([0, 0, 64], pixels[0x200 * 3..0x200 * 3 + 3]);
+    assert_eq!([255, 255, 0], pixels[0x300 * 3..0x300 * 3 + 3]);
+
+    let mut png = vec!();
+    heatmap.write_png(&mut png).unwrap();
+    assert_eq!(b"\x89PNG", &png[..4]);
+}
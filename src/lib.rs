@@ -2,16 +2,79 @@ pub mod instruction;
 pub mod chip8;
 pub mod opcode;
 pub mod sdl;
+pub mod gdb;
+pub mod dap;
+pub mod trace;
+pub mod crashdump;
+pub mod headless;
+pub mod profile;
+pub mod coverage;
+pub mod heatmap;
+pub mod replay;
+pub mod script;
+pub mod quirks;
+pub mod testrom;
+pub mod speed;
+pub mod bitmap_font;
+pub mod osd;
+pub mod debug_view;
+pub mod audio;
+pub mod clip;
+pub mod video;
+#[cfg(test)]
+mod reference;
 
 use std::collections::HashMap;
 
 pub trait StateHandler {
     fn handle_state(&mut self, state: crate::chip8::State);
+
+    /// Called whenever the emulation speed changes.
+    fn handle_speed(&mut self, _speed: crate::speed::Speed) {}
+
+    /// Called after every executed instruction, for frontends that show more
+    /// of the machine than the display.
+    fn handle_machine(&mut self, _chip8: &crate::chip8::Chip8) {}
 }
 
 pub enum ApplicationState {
     Running,
     Stopping,
+    /// Restart the program with its original memory image.
+    Reset,
+    /// Restart the CPU but keep memory and the display.
+    SoftReset,
+    /// Change the emulation speed.
+    Speed(crate::speed::Control),
+    /// Start recording a clip, or stop and save the one being recorded.
+    ToggleClip,
+}
+
+/// Where the beeper's samples go: mono, between -1 and 1, one emulated frame
+/// at a time.
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+
+    fn write_samples(&mut self, samples: &[f32]) -> std::io::Result<()>;
+
+    /// Called once the session is over.
+    fn finish(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: AudioSink + ?Sized> AudioSink for Box<S> {
+    fn sample_rate(&self) -> u32 {
+        (**self).sample_rate()
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        (**self).write_samples(samples)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        (**self).finish()
+    }
 }
 
 pub trait KeyboardHandler {
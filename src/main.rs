@@ -1,19 +1,142 @@
-use std::time::Duration;
 use std::fs::File;
 use std::io::prelude::*;
 use std::env;
+use std::panic::{self, AssertUnwindSafe};
 
-use chip8::{StateHandler, KeyboardHandler, ApplicationState};
+use chip8::{StateHandler, KeyboardHandler, ApplicationState, AudioSink};
+use chip8::chip8::{Chip8, Clock};
+use chip8::quirks::{Platform, Quirks};
+use chip8::replay::{Movie, Player, Recorder};
+use chip8::speed::Scheduler;
+use chip8::audio::{AudioOutput, NullSink, Tone, WavSink, Waveform};
+use chip8::clip::{ClipFormat, ClipRecorder, Palette, MAX_SCALE};
+use chip8::video::Y4mWriter;
+use chip8::trace::Tracer;
+
+const CYCLES_PER_FRAME: u32 = 20;
+const RECORDING_SAMPLE_RATE: u32 = 44100;
+
+struct Options {
+    romfile: String,
+    gdb_port: Option<u16>,
+    dap: bool,
+    trace: Option<String>,
+    headless: bool,
+    cycles: Option<u64>,
+    profile: bool,
+    coverage: Option<String>,
+    heatmap: Option<String>,
+    record: Option<String>,
+    replay: Option<String>,
+    script: Option<String>,
+    platform: Platform,
+    quirks: Option<Quirks>,
+    fast_forward: Option<u32>,
+    slow_motion: u32,
+    osd: bool,
+    debug_view: bool,
+    tone: Tone,
+    record_audio: Option<String>,
+    clip: Option<String>,
+    clip_scale: usize,
+    palette: Palette,
+    export_video: Option<String>,
+}
+
+enum Session {
+    Plain,
+    Record(String, Movie),
+    Replay(Movie),
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut romfile = None;
+    let mut gdb_port = None;
+    let mut dap = false;
+    let mut trace = None;
+    let mut headless = false;
+    let mut cycles = None;
+    let mut profile = false;
+    let mut coverage = None;
+    let mut heatmap = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut script = None;
+    let mut platform = Platform::Chip8;
+    let mut quirks = None;
+    let mut fast_forward = None;
+    let mut slow_motion = 4;
+    let mut osd = false;
+    let mut debug_view = false;
+    let mut tone = Tone::default();
+    let mut record_audio = None;
+    let mut clip = None;
+    let mut clip_scale = 4;
+    let mut palette = Palette::default();
+    let mut export_video = None;
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--gdb" => gdb_port = Some(args.next()?.parse().ok()?),
+            "--dap" => dap = true,
+            "--trace" => trace = Some(args.next()?.clone()),
+            "--headless" => headless = true,
+            "--cycles" => cycles = Some(args.next()?.parse().ok()?),
+            "--profile" => profile = true,
+            "--coverage" => coverage = Some(args.next()?.clone()),
+            "--heatmap" => heatmap = Some(args.next()?.clone()),
+            "--record" => record = Some(args.next()?.clone()),
+            "--replay" => replay = Some(args.next()?.clone()),
+            "--script" => script = Some(args.next()?.clone()),
+            "--platform" => platform = Platform::parse(args.next()?).map_err(|e| println!("{}", e)).ok()?,
+            "--fast-forward" => fast_forward = Some(args.next()?.parse().ok()?).filter(|factor| *factor > 0),
+            "--slow-motion" => slow_motion = args.next()?.parse().ok().filter(|factor| *factor > 0)?,
+            "--osd" => osd = true,
+            "--debug-view" => debug_view = true,
+            "--tone" => tone.frequency = args.next()?.parse().ok().filter(|frequency: &f32| *frequency > 0.0)?,
+            "--volume" => tone.volume = args.next()?.parse().ok().filter(|volume: &f32| (0.0..=1.0).contains(volume))?,
+            "--clip" => {
+                let path = args.next()?;
+                if ClipFormat::from_path(path).is_err() {
+                    println!("--clip must end in .gif or .png");
+                    return None;
+                }
+                clip = Some(path.clone());
+            },
+            "--clip-scale" => clip_scale = args.next()?.parse().ok().filter(|scale| (1..=MAX_SCALE).contains(scale)).or_else(|| {
+                println!("--clip-scale must be between 1 and {}", MAX_SCALE);
+                None
+            })?,
+            "--palette" => palette = Palette::parse(args.next()?).map_err(|e| println!("{}", e)).ok()?,
+            "--export-video" => export_video = Some(args.next()?.clone()),
+            "--record-audio" => record_audio = Some(args.next()?.clone()),
+            "--waveform" => tone.waveform = Waveform::parse(args.next()?).map_err(|e| println!("{}", e)).ok()?,
+            "--quirks" => quirks = Some(Quirks::parse(args.next()?).map_err(|e| println!("{}", e)).ok()?),
+            _ => romfile = Some(arg.clone()),
+        }
+    }
+
+    // The exported video writes its soundtrack next to it, which would fight over the file
+    if let (Some(video), Some(wav)) = (export_video.as_ref(), record_audio.as_ref()) {
+        if std::path::Path::new(video).with_extension("wav") == std::path::Path::new(wav) {
+            println!("--record-audio {} is already the soundtrack of --export-video", wav);
+            return None;
+        }
+    }
+
+    if dap {
+        return Some(Options { romfile: romfile.unwrap_or_default(), gdb_port, dap, trace, headless, cycles, profile, coverage, heatmap, record, replay, script, platform, quirks, fast_forward, slow_motion, osd, debug_view, tone, record_audio, clip, clip_scale, palette, export_video });
+    }
+
+    Some(Options { romfile: romfile?, gdb_port, dap, trace, headless, cycles, profile, coverage, heatmap, record, replay, script, platform, quirks, fast_forward, slow_motion, osd, debug_view, tone, record_audio, clip, clip_scale, palette, export_video })
+}
 
 fn read_opcodes(filename: &String) -> ([u8; 3584], usize) {
     let mut f = File::open(filename).expect("file not found");
     let mut buffer = [0u8; 3584];
 
-    let bytes_read = if let Ok(bytes_read) = f.read(&mut buffer) {
-        bytes_read
-    } else {
-        0
-    };
+    let bytes_read = f.read(&mut buffer).unwrap_or_default();
 
     (buffer, bytes_read)
 }
@@ -25,31 +148,459 @@ fn print_opcodes(buffer: &[u8], bytes_read: usize) {
     }
 }
 
-fn main() {
+fn report_crash(chip8: &Chip8, reason: &str) {
+    println!("chip8 crashed: {}", reason);
+
+    match chip8::crashdump::write_crash_dump(chip8, reason) {
+        Ok(path) => println!("crash dump written to {}", path.display()),
+        Err(e) => println!("could not write crash dump: {}", e),
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        format!("panic: {}", message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        format!("panic: {}", message)
+    } else {
+        "panic".to_string()
+    }
+}
+
+type Audio = AudioOutput<Box<dyn AudioSink>>;
+type Video = Y4mWriter<std::io::BufWriter<File>>;
+
+/// What a session records besides the trace.
+struct Outputs {
+    audio: Vec<Audio>,
+    clips: Clips,
+    video: Option<Video>,
+}
+
+/// Clip recording, started with `--clip` or toggled with a hotkey.
+struct Clips {
+    // Where the next clip goes, or None to number them in the working directory
+    path: Option<String>,
+    palette: Palette,
+    scale: usize,
+    recorder: Option<ClipRecorder>,
+}
+
+impl Clips {
+    fn toggle(&mut self) {
+        match self.recorder.take() {
+            Some(recorder) => self.save(recorder),
+            None => {
+                println!("recording clip");
+                self.recorder = Some(ClipRecorder::new(self.palette, self.scale));
+            },
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            self.save(recorder);
+        }
+    }
+
+    fn save(&mut self, recorder: ClipRecorder) {
+        let filename = self.path.take().unwrap_or_else(|| {
+            (1..).map(|n| format!("clip-{:03}.gif", n)).find(|name| !std::path::Path::new(name).exists()).unwrap()
+        });
+
+        match recorder.save(&filename) {
+            Ok(()) => println!("clip of {} frames written to {}", recorder.length(), filename),
+            Err(e) => println!("could not write clip {}: {}", filename, e),
+        }
+    }
+}
+
+fn run<E: StateHandler + KeyboardHandler>(chip8: &mut Chip8, engine: &mut E, tracer: &mut Option<Tracer>, outputs: &mut Outputs, mut scheduler: Option<Scheduler>) {
+    loop {
+        let (keyboard, keydown, application_state) = engine.handle_keyboard();
+
+        match application_state {
+            ApplicationState::Stopping => break,
+            ApplicationState::Reset => chip8.reset(),
+            ApplicationState::SoftReset => chip8.soft_reset(),
+            ApplicationState::ToggleClip => {
+                outputs.clips.toggle();
+                continue;
+            },
+            ApplicationState::Speed(control) => {
+                if let Some(scheduler) = scheduler.as_mut() {
+                    scheduler.control(control);
+                    engine.handle_speed(scheduler.speed());
+                }
+                continue;
+            },
+            ApplicationState::Running => {},
+        }
+
+        if let Some(scheduler) = scheduler.as_mut() {
+            if !scheduler.should_run() {
+                scheduler.idle();
+                continue;
+            }
+        }
+
+        let frame = chip8.frame();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| chip8.step(keyboard, keydown)));
+
+        // Traced before the result is checked, so the instruction that faulted is the last line
+        if let Some(tracer) = tracer.as_mut() {
+            tracer.record(chip8).expect("could not write trace");
+        }
+
+        let state = match result {
+            Ok(Ok(state)) => state,
+            Ok(Err(fault)) => {
+                report_crash(chip8, &fault.to_string());
+                break;
+            },
+            Err(payload) => {
+                report_crash(chip8, &panic_message(payload));
+                break;
+            },
+        };
+        engine.handle_state(state);
+        engine.handle_machine(chip8);
+
+        for audio in outputs.audio.iter_mut() {
+            audio.record(chip8).expect("could not write audio");
+        }
+
+        if chip8.frame() != frame {
+            if let Some(recorder) = outputs.clips.recorder.as_mut() {
+                recorder.capture(chip8.display());
+            }
+
+            // Written on the same frame tick as the audio, so the two line up
+            if let Some(video) = outputs.video.as_mut() {
+                video.write_frame(chip8.display()).expect("could not write video");
+            }
+        }
+
+        if let Some(scheduler) = scheduler.as_mut() {
+            scheduler.end_step();
+
+            if chip8.frame() != frame {
+                scheduler.end_frame();
+            }
+        }
+    }
+}
+
+fn run_session<E: StateHandler + KeyboardHandler>(chip8: &mut Chip8, engine: E, session: Session, tracer: &mut Option<Tracer>, outputs: &mut Outputs, scheduler: Option<Scheduler>) -> E {
+    match session {
+        Session::Plain => {
+            let mut engine = engine;
+            run(chip8, &mut engine, tracer, outputs, scheduler);
+            engine
+        },
+        Session::Record(filename, movie) => {
+            let mut recorder = Recorder::new(engine, movie);
+            run(chip8, &mut recorder, tracer, outputs, scheduler);
+
+            match recorder.movie().save(&filename) {
+                Ok(()) => println!("movie written to {}", filename),
+                Err(e) => println!("could not write movie {}: {}", filename, e),
+            }
+            recorder.into_inner()
+        },
+        Session::Replay(movie) => {
+            let mut player = Player::new(engine, movie);
+            run(chip8, &mut player, tracer, outputs, scheduler);
+            player.into_inner()
+        },
+    }
+}
+
+fn print_usage(program: &str) {
     println!("chip8 emulator by Velfolt");
+    println!("Usage: {} [options] romfile", program);
+    println!("       {} --dap", program);
+    println!();
+    println!("Options:");
+    println!("  --gdb port            wait for a gdb connection on the given port");
+    println!("  --trace file          write an instruction trace");
+    println!("  --headless            run without a window");
+    println!("  --cycles n            stop a headless run after n cycles");
+    println!("  --platform name       use the quirks of {}", Platform::NAMES.join(", "));
+    println!("  --fast-forward n      speed up n times while Tab is held, 0 for as fast as possible");
+    println!("  --slow-motion n       slow down n times in slow motion, 4 by default");
+    println!("  --osd                 show frame rate, speed and keys on screen");
+    println!("  --tone hz             beeper frequency, 440 by default");
+    println!("  --volume v            beeper volume from 0 to 1, 0.25 by default");
+    println!("  --waveform name       beeper waveform: {}", Waveform::NAMES.join(", "));
+    println!("  --clip file           record the display to an animated .gif or .png");
+    println!("  --export-video file   write the display to a .y4m video and the beeper to a matching .wav");
+    println!("  --clip-scale n        pixel size of recorded clips and videos, 4 by default");
+    println!("  --palette fg,bg       colors of recorded clips and videos as hex, ffffff,000000 by default");
+    println!("  --record-audio file   write the beeper output to a WAV file");
+    println!("  --debug-view          show disassembly, registers, stack and memory next to the game");
+    println!("  --quirks list         use only the given quirks: {}", Quirks::NAMES.join(","));
+    println!("  --script file         drive the keypad of a headless run from an input script");
+    println!("  --profile             print a profile report on exit");
+    println!("  --coverage file.json  write code and data coverage, plus a .lst listing");
+    println!("  --heatmap file.png    write a memory access heatmap");
+    println!("  --record file         record keypad input to a movie file");
+    println!("  --replay file         play back a movie file");
+    println!();
+    println!("Hotkeys:");
+    println!("  F1                    cycle the on-screen display: stats, registers, off");
+    println!("  F2                    toggle the debug layout");
+    println!("  F5                    reset and reload the ROM");
+    println!("  F6                    soft reset, keeping memory");
+    println!("  Space                 pause and resume");
+    println!("  N                     advance a single frame while paused");
+    println!("  F9                    start or stop recording a clip");
+    println!("  F10                   execute a single instruction while paused");
+    println!("  Tab                   fast-forward while held");
+    println!("  M                     toggle slow motion");
+    println!("  Escape                quit");
+}
+
+fn main() {
     let args: Vec<String> = env::args().collect();
     
-    if args.len() == 1 {
-        println!("Usage: {} romfile", args[0]);
+    let options = match parse_options(&args) {
+        Some(options) => options,
+        None => {
+            print_usage(&args[0]);
+            return;
+        }
+    };
+
+    // The debug adapter protocol owns stdout, so nothing else may be printed
+    if options.dap {
+        let stdin = std::io::BufReader::new(std::io::stdin());
+        if let Err(e) = chip8::dap::DapServer::new(std::io::stdout()).serve(stdin) {
+            eprintln!("debug adapter stopped: {}", e);
+        }
         return;
     }
+
+    println!("chip8 emulator by Velfolt");
     
-    let (buffer, bytes_read) = read_opcodes(&args[1]);
+    let (buffer, bytes_read) = read_opcodes(&options.romfile);
     print_opcodes(&buffer, bytes_read);
 
-    let mut chip8 = chip8::chip8::Chip8::new_program(buffer.to_vec());
-    let mut engine = chip8::sdl::SdlEngine::new();
+    let rom = &buffer[..bytes_read];
+    let quirks = options.quirks.unwrap_or_else(|| options.platform.quirks());
+    let mut builder = Chip8::builder().program(rom.to_vec()).quirks(quirks);
 
-    loop {
-        let (keyboard, keydown, application_state) = engine.handle_keyboard();
+    // Timers tick per emulated frame, so headless runs are deterministic and
+    // the window can be paced, paused and fast-forwarded by the scheduler
+    builder = builder.clock(Clock::Cycles(CYCLES_PER_FRAME));
+
+    let session = if let Some(filename) = options.record {
+        let seed = rand::random();
+        builder = builder.seed(seed);
+
+        Session::Record(filename, Movie::new(rom, seed, CYCLES_PER_FRAME, quirks))
+    } else if let Some(filename) = options.replay {
+        let movie = match Movie::load(&filename) {
+            Ok(movie) => movie,
+            Err(e) => {
+                println!("could not read movie {}: {}", filename, e);
+                return;
+            }
+        };
 
-        if let ApplicationState::Stopping = application_state {
-            break;
+        if movie.rom_hash != chip8::replay::rom_hash(rom) {
+            println!("warning: {} was recorded with a different ROM", filename);
         }
 
-        let state = chip8.step(keyboard, keydown);
-        engine.handle_state(state);
+        if movie.quirks != quirks {
+            println!("warning: {} was recorded with quirks \"{}\", replaying with those instead", filename, movie.quirks);
+        }
+
+        builder = builder.seed(movie.seed).quirks(movie.quirks).clock(Clock::Cycles(movie.cycles_per_frame));
+
+        Session::Replay(movie)
+    } else {
+        Session::Plain
+    };
+
+    let mut chip8 = match builder.build() {
+        Ok(chip8) => chip8,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
 
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 1200));
+    if let Some(port) = options.gdb_port {
+        let mut stub = chip8::gdb::GdbStub::new(chip8);
+        if let Err(e) = stub.listen(port) {
+            println!("gdb stub stopped: {}", e);
+        }
+        return;
+    }
+
+    let mut tracer = match options.trace {
+        Some(filename) => match Tracer::to_file(&filename) {
+            Ok(tracer) => Some(tracer),
+            Err(e) => {
+                println!("could not create trace {}: {}", filename, e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    // Rendered from the emulated sound timer, so the recording does not depend
+    // on real time and is the same for every run of a headless session
+    let mut audio: Vec<Audio> = vec!();
+    if let Some(filename) = options.record_audio.as_ref() {
+        match WavSink::create(filename, RECORDING_SAMPLE_RATE) {
+            Ok(sink) => audio.push(AudioOutput::new(options.tone, Box::new(sink))),
+            Err(e) => {
+                println!("could not create audio recording {}: {}", filename, e);
+                return;
+            }
+        }
+    }
+
+    let mut video = None;
+    if let Some(filename) = options.export_video.as_ref() {
+        let soundtrack = std::path::Path::new(filename).with_extension("wav").to_string_lossy().to_string();
+
+        let created = Y4mWriter::create(filename, options.palette, options.clip_scale)
+            .and_then(|writer| WavSink::create(&soundtrack, RECORDING_SAMPLE_RATE).map(|sink| (writer, sink)));
+
+        match created {
+            Ok((writer, sink)) => {
+                video = Some(writer);
+                audio.push(AudioOutput::new(options.tone, Box::new(sink)));
+            },
+            Err(e) => {
+                println!("could not create video {}: {}", filename, e);
+                return;
+            }
+        }
+    }
+
+    let mut clips = Clips { path: options.clip.clone(), palette: options.palette, scale: options.clip_scale, recorder: None };
+    if options.clip.is_some() {
+        clips.toggle();
+    }
+
+    let mut outputs = Outputs { audio, clips, video };
+
+    if options.profile {
+        chip8.enable_profiling();
+    }
+
+    if options.coverage.is_some() {
+        chip8.enable_coverage();
+    }
+
+    if options.heatmap.is_some() {
+        chip8.enable_heatmap();
+    }
+
+    if options.headless {
+        let engine = chip8::headless::HeadlessEngine::new(options.cycles);
+
+        let engine = match options.script {
+            Some(filename) => {
+                let script = match std::fs::read_to_string(&filename) {
+                    Ok(script) => script,
+                    Err(e) => {
+                        println!("could not read script {}: {}", filename, e);
+                        return;
+                    }
+                };
+
+                let engine = match chip8::script::ScriptedKeyboard::new(engine, &script) {
+                    Ok(engine) => engine,
+                    Err(e) => {
+                        println!("{}: {}", filename, e);
+                        return;
+                    }
+                };
+
+                run_session(&mut chip8, engine, session, &mut tracer, &mut outputs, None).into_inner()
+            },
+            None => run_session(&mut chip8, engine, session, &mut tracer, &mut outputs, None),
+        };
+
+        for row in engine.display().chunks(64) {
+            let pixels: String = row.iter().map(|pixel| if *pixel > 0 { '#' } else { '.' }).collect();
+            println!("{}", pixels);
+        }
+    } else {
+        let mut engine = chip8::sdl::SdlEngine::new();
+        if options.osd {
+            engine.set_osd(chip8::osd::OsdMode::Stats);
+        }
+        if options.debug_view {
+            engine.set_debug_view(true);
+        }
+
+        let sink: Box<dyn AudioSink> = match engine.open_audio() {
+            Ok(sink) => Box::new(sink),
+            Err(e) => {
+                println!("could not open audio device, running without sound: {}", e);
+                Box::new(NullSink::new(44100))
+            },
+        };
+        outputs.audio.push(AudioOutput::new(options.tone, sink));
+
+        let scheduler = Scheduler::new(options.fast_forward, options.slow_motion);
+        run_session(&mut chip8, engine, session, &mut tracer, &mut outputs, Some(scheduler));
+    }
+
+    let Outputs { audio, mut clips, video } = outputs;
+    clips.finish();
+
+    for audio in audio {
+        if let Err(e) = audio.into_sink().finish() {
+            println!("could not write audio: {}", e);
+        }
+    }
+
+    if let Some(filename) = options.record_audio {
+        println!("audio written to {}", filename);
+    }
+
+    if let (Some(filename), Some(video)) = (options.export_video, video) {
+        let frames = video.frames();
+
+        match video.into_inner() {
+            Ok(_) => println!("video of {} frames written to {} with audio in {}", frames, filename, std::path::Path::new(&filename).with_extension("wav").display()),
+            Err(e) => println!("could not write video {}: {}", filename, e),
+        }
+    }
+
+    if let Some(profile) = chip8.profile() {
+        print!("{}", profile.report(&chip8, 10));
+    }
+
+    if let (Some(filename), Some(coverage)) = (options.coverage, chip8.coverage()) {
+        let end = 0x200 + bytes_read as u16;
+        let listing = std::path::Path::new(&filename).with_extension("lst");
+
+        let written = std::fs::write(&filename, format!("{:#}\n", coverage.to_json(0x200, end)))
+            .and_then(|_| std::fs::write(&listing, coverage.listing(&chip8, 0x200, end)));
+
+        match written {
+            Ok(()) => println!("coverage written to {} and {}", filename, listing.display()),
+            Err(e) => println!("could not write coverage: {}", e),
+        }
+    }
+
+    if let (Some(filename), Some(heatmap)) = (options.heatmap, chip8.heatmap()) {
+        let written = File::create(&filename)
+            .map_err(|e| e.to_string())
+            .and_then(|file| heatmap.write_png(std::io::BufWriter::new(file)).map_err(|e| e.to_string()));
+
+        match written {
+            Ok(()) => println!("heatmap written to {}", filename),
+            Err(e) => println!("could not write heatmap: {}", e),
+        }
     }
 }
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum OpCode {
     NOOP,
@@ -32,4 +34,91 @@ pub enum OpCode {
     LDB { vx: u16 },
     LDMEMI { vx: u16 },
     LDVXMEMI { vx: u16 },
-}
\ No newline at end of file
+}
+impl OpCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::NOOP => "NOOP",
+            OpCode::CLS => "CLS",
+            OpCode::RET => "RET",
+            OpCode::JP { .. } => "JP",
+            OpCode::CALL { .. } => "CALL",
+            OpCode::SE { .. } => "SE",
+            OpCode::SNE { .. } => "SNE",
+            OpCode::LD { .. } => "LD",
+            OpCode::ADD { .. } => "ADD",
+            OpCode::OR { .. } => "OR",
+            OpCode::AND { .. } => "AND",
+            OpCode::XOR { .. } => "XOR",
+            OpCode::ADDREG { .. } => "ADDREG",
+            OpCode::SUB { .. } => "SUB",
+            OpCode::SHR { .. } => "SHR",
+            OpCode::SUBN { .. } => "SUBN",
+            OpCode::SHL { .. } => "SHL",
+            OpCode::LDI { .. } => "LDI",
+            OpCode::JPV0 { .. } => "JPV0",
+            OpCode::RND { .. } => "RND",
+            OpCode::DRW { .. } => "DRW",
+            OpCode::SKP { .. } => "SKP",
+            OpCode::SKNP { .. } => "SKNP",
+            OpCode::LDVXDT { .. } => "LDVXDT",
+            OpCode::LDK { .. } => "LDK",
+            OpCode::LDDTVX { .. } => "LDDTVX",
+            OpCode::LDSTVX { .. } => "LDSTVX",
+            OpCode::ADDI { .. } => "ADDI",
+            OpCode::LDF { .. } => "LDF",
+            OpCode::LDB { .. } => "LDB",
+            OpCode::LDMEMI { .. } => "LDMEMI",
+            OpCode::LDVXMEMI { .. } => "LDVXMEMI",
+        }
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let operand = |other: u16, by_value: bool| if by_value { format!("0x{:02X}", other) } else { format!("V{:X}", other) };
+
+        match *self {
+            OpCode::NOOP => write!(fmt, "NOOP"),
+            OpCode::CLS => write!(fmt, "CLS"),
+            OpCode::RET => write!(fmt, "RET"),
+            OpCode::JP { addr } => write!(fmt, "JP 0x{:03X}", addr),
+            OpCode::CALL { addr } => write!(fmt, "CALL 0x{:03X}", addr),
+            OpCode::SE { vx, other, by_value } => write!(fmt, "SE V{:X}, {}", vx, operand(other, by_value)),
+            OpCode::SNE { vx, other, by_value } => write!(fmt, "SNE V{:X}, {}", vx, operand(other, by_value)),
+            OpCode::LD { vx, other, by_value } => write!(fmt, "LD V{:X}, {}", vx, operand(other, by_value)),
+            OpCode::ADD { vx, byte } => write!(fmt, "ADD V{:X}, 0x{:02X}", vx, byte),
+            OpCode::OR { vx, vy } => write!(fmt, "OR V{:X}, V{:X}", vx, vy),
+            OpCode::AND { vx, vy } => write!(fmt, "AND V{:X}, V{:X}", vx, vy),
+            OpCode::XOR { vx, vy } => write!(fmt, "XOR V{:X}, V{:X}", vx, vy),
+            OpCode::ADDREG { vx, vy } => write!(fmt, "ADD V{:X}, V{:X}", vx, vy),
+            OpCode::SUB { vx, vy } => write!(fmt, "SUB V{:X}, V{:X}", vx, vy),
+            OpCode::SHR { vx, vy } => write!(fmt, "SHR V{:X}, V{:X}", vx, vy),
+            OpCode::SUBN { vx, vy } => write!(fmt, "SUBN V{:X}, V{:X}", vx, vy),
+            OpCode::SHL { vx, vy } => write!(fmt, "SHL V{:X}, V{:X}", vx, vy),
+            OpCode::LDI { addr } => write!(fmt, "LD I, 0x{:03X}", addr),
+            OpCode::JPV0 { addr } => write!(fmt, "JP V0, 0x{:03X}", addr),
+            OpCode::RND { vx, byte } => write!(fmt, "RND V{:X}, 0x{:02X}", vx, byte),
+            OpCode::DRW { vx, vy, nibble } => write!(fmt, "DRW V{:X}, V{:X}, {}", vx, vy, nibble),
+            OpCode::SKP { vx } => write!(fmt, "SKP V{:X}", vx),
+            OpCode::SKNP { vx } => write!(fmt, "SKNP V{:X}", vx),
+            OpCode::LDVXDT { vx } => write!(fmt, "LD V{:X}, DT", vx),
+            OpCode::LDK { vx } => write!(fmt, "LD V{:X}, K", vx),
+            OpCode::LDDTVX { vx } => write!(fmt, "LD DT, V{:X}", vx),
+            OpCode::LDSTVX { vx } => write!(fmt, "LD ST, V{:X}", vx),
+            OpCode::ADDI { vx } => write!(fmt, "ADD I, V{:X}", vx),
+            OpCode::LDF { vx } => write!(fmt, "LD F, V{:X}", vx),
+            OpCode::LDB { vx } => write!(fmt, "LD B, V{:X}", vx),
+            OpCode::LDMEMI { vx } => write!(fmt, "LD [I], V{:X}", vx),
+            OpCode::LDVXMEMI { vx } => write!(fmt, "LD V{:X}, [I]", vx),
+        }
+    }
+}
+
+#[test]
+fn test_display() {
+    assert_eq!("LD V3, 0x2A", OpCode::LD { vx: 3, other: 0x2A, by_value: true }.to_string());
+    assert_eq!("SE VA, VB", OpCode::SE { vx: 0xA, other: 0xB, by_value: false }.to_string());
+    assert_eq!("DRW V0, V1, 5", OpCode::DRW { vx: 0, vy: 1, nibble: 5 }.to_string());
+    assert_eq!("LD V2, [I]", OpCode::LDVXMEMI { vx: 2 }.to_string());
+}
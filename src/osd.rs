@@ -0,0 +1,163 @@
+use crate::chip8::Chip8;
+use crate::speed::Speed;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OsdMode {
+    Off,
+    /// Frame rate, instruction rate, speed and pressed keys.
+    Stats,
+    /// The stats plus V0-VF, I and PC.
+    Registers,
+}
+
+impl OsdMode {
+    pub fn next(self) -> OsdMode {
+        match self {
+            OsdMode::Off => OsdMode::Stats,
+            OsdMode::Stats => OsdMode::Registers,
+            OsdMode::Registers => OsdMode::Off,
+        }
+    }
+}
+
+/// Collects what the on-screen display shows. The frontend feeds it every
+/// executed instruction and draws the lines it produces.
+pub struct Osd {
+    mode: OsdMode,
+    speed: Speed,
+    frame: u64,
+    frames: u64,
+    instructions: u64,
+    since: Instant,
+    fps: f64,
+    ips: f64,
+    registers: [u8; 16],
+    i: u16,
+    pc: u16,
+}
+
+impl Osd {
+    pub fn new(mode: OsdMode) -> Self {
+        Osd {
+            mode,
+            speed: Speed::Normal,
+            frame: 0,
+            frames: 0,
+            instructions: 0,
+            since: Instant::now(),
+            fps: 0.0,
+            ips: 0.0,
+            registers: [0; 16],
+            i: 0,
+            pc: 0,
+        }
+    }
+
+    pub fn mode(&self) -> OsdMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: OsdMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+    }
+
+    /// Counts one executed instruction. Returns true when a new frame started,
+    /// which is when the display is worth redrawing.
+    pub fn record_step(&mut self, frame: u64) -> bool {
+        self.instructions += 1;
+
+        if frame == self.frame {
+            return false;
+        }
+
+        self.frame = frame;
+        self.frames += 1;
+
+        let elapsed = self.since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frames as f64 / elapsed.as_secs_f64();
+            self.ips = self.instructions as f64 / elapsed.as_secs_f64();
+            self.frames = 0;
+            self.instructions = 0;
+            self.since = Instant::now();
+        }
+
+        true
+    }
+
+    pub fn record_machine(&mut self, chip8: &Chip8) {
+        if self.mode == OsdMode::Registers {
+            self.registers = *chip8.registers();
+            self.i = chip8.i();
+            self.pc = chip8.pc();
+        }
+    }
+
+    pub fn lines(&self, keyboard: &HashMap<u8, bool>) -> Vec<String> {
+        if self.mode == OsdMode::Off {
+            return vec!();
+        }
+
+        let mut pressed: Vec<u8> = keyboard.iter().filter(|(_, down)| **down).map(|(key, _)| *key).collect();
+        pressed.sort_unstable();
+        let keys: Vec<String> = pressed.iter().map(|key| format!("{:X}", key)).collect();
+
+        let mut lines = vec!(
+            format!("FPS {:.0}  IPS {:.0}", self.fps, self.ips),
+            format!("SPEED {}", multiplier(self.speed)),
+            format!("KEYS {}", if keys.is_empty() { "-".to_string() } else { keys.join(" ") }),
+        );
+
+        if self.mode == OsdMode::Registers {
+            for (name, registers) in ["V0-7", "V8-F"].iter().zip(self.registers.chunks(8)) {
+                let values: Vec<String> = registers.iter().map(|value| format!("{:02X}", value)).collect();
+                lines.push(format!("{} {}", name, values.join(" ")));
+            }
+            lines.push(format!("I {:04X}  PC {:04X}", self.i, self.pc));
+        }
+
+        lines
+    }
+}
+
+fn multiplier(speed: Speed) -> String {
+    match speed {
+        Speed::Normal => "1X".to_string(),
+        Speed::Paused => "0X PAUSED".to_string(),
+        Speed::FastForward(None) => "MAX".to_string(),
+        Speed::FastForward(Some(factor)) => format!("{}X", factor),
+        Speed::SlowMotion(factor) => format!("1/{}X", factor),
+    }
+}
+
+#[test]
+fn test_lines() {
+    let mut osd = Osd::new(OsdMode::Off);
+    let mut keyboard: HashMap<u8, bool> = (0..16).map(|key| (key, false)).collect();
+    assert!(osd.lines(&keyboard).is_empty());
+
+    osd.set_mode(OsdMode::Stats.next());
+    osd.set_speed(Speed::SlowMotion(4));
+    keyboard.insert(0xA, true);
+    keyboard.insert(5, true);
+
+    let mut chip8 = Chip8::new_program(vec!());
+    chip8.set_register(0xF, 0xAB);
+    osd.record_machine(&chip8);
+
+    assert!(!osd.record_step(0));
+    assert!(osd.record_step(1));
+
+    let lines = osd.lines(&keyboard);
+    assert_eq!("SPEED 1/4X", lines[1]);
+    assert_eq!("KEYS 5 A", lines[2]);
+    assert_eq!("V8-F 00 00 00 00 00 00 00 AB", lines[4]);
+    assert_eq!("I 0000  PC 0200", lines[5]);
+}
@@ -0,0 +1,160 @@
+use crate::chip8::Chip8;
+use crate::opcode::OpCode;
+
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+
+/// Execution counters collected by `Chip8` once profiling is enabled.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    executions: Vec<u64>,
+    opcodes: HashMap<&'static str, u64>,
+    draws_per_frame: Vec<u32>,
+    frame_draws: u32,
+    frames_blocked: u64,
+}
+
+struct Region {
+    start: u16,
+    end: u16,
+    executions: u64,
+}
+
+impl Profile {
+    pub fn new(memory_size: usize) -> Self {
+        Profile {
+            executions: vec![0; memory_size],
+            opcodes: HashMap::new(),
+            draws_per_frame: vec!(),
+            frame_draws: 0,
+            frames_blocked: 0,
+        }
+    }
+
+    pub(crate) fn record_instruction(&mut self, addr: u16, opcode: &OpCode) {
+        self.executions[addr as usize] += 1;
+        *self.opcodes.entry(opcode.name()).or_insert(0) += 1;
+
+        if let OpCode::DRW { .. } = opcode {
+            self.frame_draws += 1;
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self, blocked: bool) {
+        self.draws_per_frame.push(self.frame_draws);
+        self.frame_draws = 0;
+
+        if blocked {
+            self.frames_blocked += 1;
+        }
+    }
+
+    pub fn executions(&self, addr: u16) -> u64 {
+        self.executions.get(addr as usize).copied().unwrap_or_default()
+    }
+
+    pub fn opcode_executions(&self, name: &str) -> u64 {
+        self.opcodes.get(name).copied().unwrap_or_default()
+    }
+
+    pub fn draws_per_frame(&self) -> &[u32] {
+        &self.draws_per_frame
+    }
+
+    pub fn frames_blocked(&self) -> u64 {
+        self.frames_blocked
+    }
+
+    // Runs of consecutively executed instructions, hottest first
+    fn regions(&self) -> Vec<Region> {
+        let mut regions: Vec<Region> = vec!();
+
+        for (addr, executions) in self.executions.iter().enumerate().filter(|(_, count)| **count > 0) {
+            let addr = addr as u16;
+
+            match regions.last_mut() {
+                Some(region) if region.end + 2 == addr => {
+                    region.end = addr;
+                    region.executions += executions;
+                },
+                _ => regions.push(Region { start: addr, end: addr, executions: *executions }),
+            }
+        }
+
+        regions.sort_by_key(|region| std::cmp::Reverse(region.executions));
+        regions
+    }
+
+    pub fn report(&self, chip8: &Chip8, max_regions: usize) -> String {
+        let mut report = String::new();
+        let total: u64 = self.executions.iter().sum();
+        let percent = |count: u64| if total > 0 { count as f64 * 100.0 / total as f64 } else { 0.0 };
+
+        writeln!(report, "instructions executed: {}", total).unwrap();
+
+        writeln!(report, "\nhottest regions:").unwrap();
+        for region in self.regions().iter().take(max_regions) {
+            writeln!(report, "  0x{:04X}-0x{:04X} {:>10} {:6.2}%", region.start, region.end, region.executions, percent(region.executions)).unwrap();
+
+            for addr in (region.start..=region.end).step_by(2) {
+                writeln!(report, "    0x{:04X} {:>10}  {}", addr, self.executions(addr), chip8.read_opcode_at(addr)).unwrap();
+            }
+        }
+
+        writeln!(report, "\nopcodes:").unwrap();
+        let mut opcodes: Vec<(&&str, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (name, count) in opcodes {
+            writeln!(report, "  {:<10} {:>10} {:6.2}%", name, count, percent(*count)).unwrap();
+        }
+
+        let frames = self.draws_per_frame.len() as u64;
+        let draws: u64 = self.draws_per_frame.iter().map(|draws| *draws as u64).sum();
+        let max_draws = self.draws_per_frame.iter().max().copied().unwrap_or_default();
+        let average_draws = if frames > 0 { draws as f64 / frames as f64 } else { 0.0 };
+
+        writeln!(report, "\nframes: {}", frames).unwrap();
+        writeln!(report, "  DRW per frame: {:.2} average, {} max", average_draws, max_draws).unwrap();
+        writeln!(report, "  blocked waiting for input: {}", self.frames_blocked).unwrap();
+
+        report
+    }
+}
+
+#[test]
+fn test_profile_counts() {
+    // LD V0, 0x01; DRW V0, V0, 1; JP 0x202
+    let mut chip8 = Chip8::new_program(vec!(0x60, 0x01, 0xD0, 0x01, 0x12, 0x02));
+    chip8.enable_profiling();
+
+    for _ in 0..9 {
+        chip8.step(&HashMap::new(), None).unwrap();
+    }
+    chip8.tick_frame();
+
+    let profile = chip8.profile().unwrap();
+    assert_eq!(1, profile.executions(0x200));
+    assert_eq!(4, profile.executions(0x202));
+    assert_eq!(4, profile.executions(0x204));
+    assert_eq!(4, profile.opcode_executions("DRW"));
+    assert_eq!(4, profile.opcode_executions("JP"));
+    assert_eq!(4, profile.draws_per_frame().iter().sum::<u32>());
+
+    let report = profile.report(&chip8, 1);
+    assert!(report.contains("instructions executed: 9"));
+    assert!(report.contains("  0x0200-0x0204          9 100.00%"));
+    assert!(report.contains("    0x0202          4  DRW V0, V0, 1"));
+}
+
+#[test]
+fn test_profile_blocked_frames() {
+    // LD V0, K
+    let mut chip8 = Chip8::new_program(vec!(0xF0, 0x0A));
+    chip8.enable_profiling();
+
+    chip8.step(&HashMap::new(), None).unwrap();
+    chip8.tick_frame();
+    chip8.tick_frame();
+
+    assert!(chip8.profile().unwrap().frames_blocked() >= 2);
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+
+/// Behaviours that differ between CHIP-8 interpreters. The defaults match this
+/// emulator's original behaviour.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Quirks {
+    /// SHR and SHL shift VY into VX, like the COSMAC VIP, instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// LD [I], VX and LD VX, [I] leave I pointing past the last register.
+    pub load_store_increments_i: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// OR, AND and XOR reset VF to zero.
+    pub logic_resets_vf: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    pub const NAMES: [&'static str; 5] = ["shift-vy", "load-store-i", "jump-vx", "vf-reset", "clip"];
+
+    /// Parses a comma separated list of quirk names, e.g. `shift-vy,clip`.
+    pub fn parse(list: &str) -> Result<Quirks, String> {
+        let mut quirks = Quirks::default();
+
+        for name in list.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            match name {
+                "shift-vy" => quirks.shift_uses_vy = true,
+                "load-store-i" => quirks.load_store_increments_i = true,
+                "jump-vx" => quirks.jump_uses_vx = true,
+                "vf-reset" => quirks.logic_resets_vf = true,
+                "clip" => quirks.clip_sprites = true,
+                _ => return Err(format!("unknown quirk {}, expected one of {}", name, Quirks::NAMES.join(", "))),
+            }
+        }
+
+        Ok(quirks)
+    }
+}
+
+/// The enabled quirks as a comma separated list that `Quirks::parse` reads back.
+impl fmt::Display for Quirks {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let enabled = [self.shift_uses_vy, self.load_store_increments_i, self.jump_uses_vx, self.logic_resets_vf, self.clip_sprites];
+        let names: Vec<&str> = Quirks::NAMES.iter().zip(enabled.iter()).filter(|(_, on)| **on).map(|(name, _)| *name).collect();
+
+        write!(fmt, "{}", names.join(","))
+    }
+}
+
+/// Interpreters whose quirks are well known.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Platform {
+    /// This emulator's original behaviour, which most modern games expect.
+    Chip8,
+    /// The original COSMAC VIP interpreter.
+    CosmacVip,
+    /// SUPER-CHIP 1.1 on the HP48.
+    SuperChip,
+}
+
+impl Platform {
+    pub const NAMES: [&'static str; 3] = ["chip8", "vip", "schip"];
+
+    pub fn parse(name: &str) -> Result<Platform, String> {
+        match name {
+            "chip8" => Ok(Platform::Chip8),
+            "vip" => Ok(Platform::CosmacVip),
+            "schip" => Ok(Platform::SuperChip),
+            _ => Err(format!("unknown platform {}, expected one of {}", name, Platform::NAMES.join(", "))),
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::default(),
+            Platform::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                logic_resets_vf: true,
+                clip_sprites: true,
+                ..Quirks::default()
+            },
+            Platform::SuperChip => Quirks { jump_uses_vx: true, clip_sprites: true, ..Quirks::default() },
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Ok(Quirks::default()), Quirks::parse(""));
+    assert_eq!(
+        Ok(Quirks { shift_uses_vy: true, clip_sprites: true, ..Quirks::default() }),
+        Quirks::parse("shift-vy, clip"));
+    assert!(Quirks::parse("shift-vy,fast").is_err());
+}
+
+#[test]
+fn test_display_roundtrip() {
+    let quirks = Quirks { shift_uses_vy: true, logic_resets_vf: true, clip_sprites: true, ..Quirks::default() };
+
+    assert_eq!("", Quirks::default().to_string());
+    assert_eq!("shift-vy,vf-reset,clip", quirks.to_string());
+    assert_eq!(Ok(quirks), Quirks::parse(&quirks.to_string()));
+}
@@ -0,0 +1,292 @@
+//! A deliberately simple second implementation of the CHIP-8 instruction set,
+//! written straight from the spec and decoding raw words itself. Random
+//! instruction sequences are run through it and through `Chip8::apply`, and
+//! the full machine state is compared after every instruction, once for
+//! each platform's quirks.
+
+use crate::chip8::{Chip8, FONT};
+use crate::instruction::Instruction;
+use crate::quirks::{Platform, Quirks};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use std::collections::HashMap;
+
+#[derive(Clone)]
+struct Reference {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    /// Return addresses, i.e. the instruction after each CALL.
+    stack: Vec<u16>,
+    delay_timer: u8,
+    sound_timer: u8,
+    memory: Vec<u8>,
+    display: Vec<u8>,
+    quirks: Quirks,
+}
+
+impl Reference {
+    fn new(quirks: Quirks) -> Self {
+        let mut memory = vec![0; 4096];
+        memory[..FONT.len()].copy_from_slice(&FONT);
+
+        Reference { v: [0; 16], i: 0, pc: 0x200, stack: vec!(), delay_timer: 0, sound_timer: 0, memory, display: vec![0; 64 * 32], quirks }
+    }
+
+    fn load(&self, addr: u32) -> Option<u8> {
+        self.memory.get(addr as usize).copied()
+    }
+
+    /// Executes one instruction. `None` means the machine faulted.
+    fn execute(&mut self, word: u16, keyboard: &HashMap<u8, bool>) -> Option<()> {
+        let x = (word >> 8 & 0xF) as usize;
+        let y = (word >> 4 & 0xF) as usize;
+        let n = word & 0xF;
+        let nn = (word & 0xFF) as u8;
+        let nnn = word & 0xFFF;
+        let key = |value: u8| keyboard.get(&value) == Some(&true);
+
+        let mut next = self.pc + 2;
+
+        match (word >> 12, n) {
+            (0x0, _) if word == 0x00E0 => self.display.iter_mut().for_each(|pixel| *pixel = 0),
+            (0x0, _) if word == 0x00EE => next = self.stack.pop()?,
+            (0x0, _) => {},
+            (0x1, _) => next = nnn,
+            (0x2, _) => {
+                if self.stack.len() == 16 {
+                    return None;
+                }
+                self.stack.push(next);
+                next = nnn;
+            },
+            (0x3, _) if self.v[x] == nn => next += 2,
+            (0x4, _) if self.v[x] != nn => next += 2,
+            (0x5, 0) if self.v[x] == self.v[y] => next += 2,
+            (0x6, _) => self.v[x] = nn,
+            (0x7, _) => self.v[x] = self.v[x].wrapping_add(nn),
+            (0x8, 0x0) => self.v[x] = self.v[y],
+            (0x8, 0x1..=0x3) => {
+                self.v[x] = match n {
+                    0x1 => self.v[x] | self.v[y],
+                    0x2 => self.v[x] & self.v[y],
+                    _ => self.v[x] ^ self.v[y],
+                };
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
+            },
+            (0x8, 0x4) => {
+                let sum = self.v[x] as u32 + self.v[y] as u32;
+                self.v[x] = (sum & 0xFF) as u8;
+                self.v[0xF] = if sum > 0xFF { 1 } else { 0 };
+            },
+            (0x8, 0x5) => {
+                let flag = if self.v[x] >= self.v[y] { 1 } else { 0 };
+                self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+                self.v[0xF] = flag;
+            },
+            (0x8, 0x6) => {
+                let value = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+                self.v[x] = value / 2;
+                self.v[0xF] = value & 1;
+            },
+            (0x8, 0x7) => {
+                let flag = if self.v[y] >= self.v[x] { 1 } else { 0 };
+                self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+                self.v[0xF] = flag;
+            },
+            (0x8, 0xE) => {
+                let value = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+                self.v[x] = value.wrapping_mul(2);
+                self.v[0xF] = value / 128;
+            },
+            (0x9, 0) if self.v[x] != self.v[y] => next += 2,
+            (0xA, _) => self.i = nnn,
+            (0xB, _) if self.quirks.jump_uses_vx => next = nnn + self.v[x] as u16,
+            (0xB, _) => next = nnn + self.v[0] as u16,
+            (0xD, _) => {
+                let mut collision = 0;
+
+                let (left, top) = (self.v[x] as usize % 64, self.v[y] as usize % 32);
+
+                for row in 0..n as usize {
+                    let sprite = self.load(self.i as u32 + row as u32)?;
+
+                    for column in 0..8 {
+                        let clipped = self.quirks.clip_sprites && (left + column >= 64 || top + row >= 32);
+
+                        if sprite & (0x80 >> column) != 0 && !clipped {
+                            let pixel = (top + row) % 32 * 64 + (left + column) % 64;
+                            if self.display[pixel] == 1 {
+                                collision = 1;
+                            }
+                            self.display[pixel] ^= 1;
+                        }
+                    }
+                }
+
+                self.v[0xF] = collision;
+            },
+            (0xE, _) if nn == 0x9E && key(self.v[x]) => next += 2,
+            (0xE, _) if nn == 0xA1 && !key(self.v[x]) => next += 2,
+            (0xF, _) if nn == 0x07 => self.v[x] = self.delay_timer,
+            (0xF, _) if nn == 0x15 => self.delay_timer = self.v[x],
+            (0xF, _) if nn == 0x18 => self.sound_timer = self.v[x],
+            (0xF, _) if nn == 0x1E => self.i = self.i.wrapping_add(self.v[x] as u16),
+            (0xF, _) if nn == 0x29 => self.i = self.v[x] as u16 * 5,
+            (0xF, _) if nn == 0x33 => {
+                let digits = [self.v[x] / 100, self.v[x] / 10 % 10, self.v[x] % 10];
+                self.load(self.i as u32 + 2)?;
+                self.memory[self.i as usize..self.i as usize + 3].copy_from_slice(&digits);
+            },
+            (0xF, _) if nn == 0x55 => {
+                for register in 0..=x {
+                    self.load(self.i as u32 + register as u32)?;
+                    self.memory[self.i as usize + register] = self.v[register];
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            },
+            (0xF, _) if nn == 0x65 => {
+                for register in 0..=x {
+                    self.v[register] = self.load(self.i as u32 + register as u32)?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            },
+            _ => {},
+        }
+
+        self.pc = next;
+        Some(())
+    }
+}
+
+/// The first difference between the two machines, if any.
+fn difference(chip8: &Chip8, reference: &Reference) -> Option<String> {
+    // Chip8 keeps the address of the CALL itself on its stack
+    let stack: Vec<u16> = chip8.stack.iter().map(|addr| addr + 2).collect();
+
+    if chip8.v != reference.v {
+        Some(format!("V: {:02X?} != {:02X?}", chip8.v, reference.v))
+    } else if chip8.i != reference.i {
+        Some(format!("I: {:04X} != {:04X}", chip8.i, reference.i))
+    } else if chip8.pc != reference.pc {
+        Some(format!("PC: {:04X} != {:04X}", chip8.pc, reference.pc))
+    } else if stack != reference.stack {
+        Some(format!("stack: {:04X?} != {:04X?}", stack, reference.stack))
+    } else if (chip8.delay_timer, chip8.sound_timer) != (reference.delay_timer, reference.sound_timer) {
+        Some(format!("timers: {:?} != {:?}", (chip8.delay_timer, chip8.sound_timer), (reference.delay_timer, reference.sound_timer)))
+    } else if let Some(addr) = (0..4096).find(|addr| chip8.memory[*addr] != reference.memory[*addr]) {
+        Some(format!("memory at {:04X}: {:02X} != {:02X}", addr, chip8.memory[addr], reference.memory[addr]))
+    } else {
+        (0..64 * 32)
+            .find(|pixel| chip8.display[*pixel] != reference.display[*pixel])
+            .map(|pixel| format!("display at ({}, {}): {} != {}", pixel % 64, pixel / 64, chip8.display[pixel], reference.display[pixel]))
+    }
+}
+
+/// Runs a sequence through both machines, returning the index of the first
+/// instruction they disagree on and a description of the difference.
+fn run(words: &[u16], keyboard: &HashMap<u8, bool>, quirks: Quirks) -> Option<(usize, String)> {
+    let mut chip8 = Chip8::builder().quirks(quirks).build().unwrap();
+    let mut reference = Reference::new(quirks);
+
+    for (index, word) in words.iter().enumerate() {
+        let result = chip8.apply(Instruction::from(*word).into(), keyboard);
+        let expected = reference.execute(*word, keyboard);
+
+        match (result, expected) {
+            (Ok(()), Some(())) => {},
+            (Err(_), None) => return None,
+            (result, expected) => return Some((index, format!("fault: {:?} != {:?}", result, expected))),
+        }
+
+        if let Some(difference) = difference(&chip8, &reference) {
+            return Some((index, difference));
+        }
+    }
+
+    None
+}
+
+/// Drops instructions from a failing sequence for as long as it keeps failing.
+fn shrink(mut words: Vec<u16>, keyboard: &HashMap<u8, bool>, quirks: Quirks) -> Vec<u16> {
+    let mut index = 0;
+
+    while index < words.len() {
+        let mut candidate = words.clone();
+        candidate.remove(index);
+
+        if run(&candidate, keyboard, quirks).is_some() {
+            words = candidate;
+        } else {
+            index += 1;
+        }
+    }
+
+    words
+}
+
+fn random_word(rng: &mut StdRng) -> u16 {
+    let x = rng.gen_range(0, 16) << 8;
+    let y = rng.gen_range(0, 16) << 4;
+    let nn = rng.gen::<u8>() as u16;
+
+    // RND and LD K are left out: one is random and the other halts the machine
+    match rng.gen_range(0, 32) {
+        0 => 0x00E0,
+        1 => 0x00EE,
+        2 => 0x1000 | rng.gen_range(0x200, 0x1000),
+        3 => 0x2000 | rng.gen_range(0x200, 0x1000),
+        4 => 0x3000 | x | nn,
+        5 => 0x4000 | x | nn,
+        6 => 0x5000 | x | y,
+        7 | 8 => 0x6000 | x | nn,
+        9 => 0x7000 | x | nn,
+        10..=18 => 0x8000 | x | y | [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE][rng.gen_range(0, 9)],
+        19 => 0x9000 | x | y,
+        20 | 21 => 0xA000 | rng.gen_range(0, 0x1000),
+        22 => 0xB000 | rng.gen_range(0x200, 0xF00),
+        23 | 24 => 0xD000 | x | y | rng.gen_range(0, 16),
+        25 => 0xE09E | x,
+        26 => 0xE0A1 | x,
+        27 => 0xF007 | x | [0x00, 0x0E, 0x11][rng.gen_range(0, 3)],
+        28 => 0xF01E | x,
+        29 => 0xF029 | x,
+        30 => 0xF033 | x,
+        _ => 0xF055 | x | [0x00, 0x10][rng.gen_range(0, 2)],
+    }
+}
+
+#[test]
+fn test_reference_agrees() {
+    let mut keyboard = HashMap::new();
+    keyboard.insert(3, true);
+    keyboard.insert(7, true);
+    keyboard.insert(0xF, false);
+
+    for platform in [Platform::Chip8, Platform::CosmacVip, Platform::SuperChip].iter() {
+        let quirks = platform.quirks();
+
+        for seed in 0..500 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let words: Vec<u16> = (0..64).map(|_| random_word(&mut rng)).collect();
+
+            if run(&words, &keyboard, quirks).is_some() {
+                let words = shrink(words, &keyboard, quirks);
+                let (index, difference) = run(&words, &keyboard, quirks).unwrap();
+                let listing: Vec<String> = words.iter()
+                    .map(|word| format!("{:04X}  {}", word, crate::opcode::OpCode::from(Instruction::from(*word))))
+                    .collect();
+
+                panic!("{:?} seed {}: instruction {} differs, {}\n{}", platform, seed, index, difference, listing.join("\n"));
+            }
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use crate::{ApplicationState, KeyboardHandler, StateHandler};
+use crate::chip8::{Chip8, State};
+use crate::quirks::Quirks;
+use crate::speed::Speed;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAGIC: &str = "chip8-movie 1";
+
+/// A recorded play session: the keypad state for every frame, plus everything
+/// else needed to reproduce the run exactly.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub cycles_per_frame: u32,
+    pub quirks: Quirks,
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], seed: u64, cycles_per_frame: u32, quirks: Quirks) -> Self {
+        Movie { rom_hash: rom_hash(rom), seed, cycles_per_frame, quirks, frames: vec!() }
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines();
+
+        if lines.next() != Some(MAGIC) {
+            return Err("not a chip8 movie".to_string());
+        }
+
+        let mut header = |name: &str| -> Result<String, String> {
+            match lines.next().map(|line| line.splitn(2, ' ').collect::<Vec<&str>>()) {
+                Some(ref parts) if parts[0] == name => Ok(parts.get(1).copied().unwrap_or_default().to_string()),
+                _ => Err(format!("missing {}", name)),
+            }
+        };
+
+        let rom_hash = u64::from_str_radix(&header("rom")?, 16).map_err(|e| e.to_string())?;
+        let seed = header("seed")?.parse().map_err(|e: std::num::ParseIntError| e.to_string())?;
+        let cycles_per_frame = header("cycles-per-frame")?.parse().map_err(|e: std::num::ParseIntError| e.to_string())?;
+        let quirks = Quirks::parse(&header("quirks")?)?;
+
+        let frames = lines
+            .filter(|line| !line.is_empty())
+            .map(|line| u16::from_str_radix(line, 16).map_err(|e| format!("bad frame {}: {}", line, e)))
+            .collect::<Result<Vec<u16>, String>>()?;
+
+        Ok(Movie { rom_hash, seed, cycles_per_frame, quirks, frames })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Movie> {
+        Movie::parse(&fs::read_to_string(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmt, "{}", MAGIC)?;
+        writeln!(fmt, "rom {:016x}", self.rom_hash)?;
+        writeln!(fmt, "seed {}", self.seed)?;
+        writeln!(fmt, "cycles-per-frame {}", self.cycles_per_frame)?;
+        writeln!(fmt, "quirks {}", self.quirks)?;
+
+        for keys in &self.frames {
+            writeln!(fmt, "{:04X}", keys)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a hash of a ROM image.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// Resets are not part of the movie, so they are ignored while recording and
+// playing back rather than letting the two runs drift apart.
+fn without_reset(application_state: ApplicationState) -> ApplicationState {
+    match application_state {
+        ApplicationState::Reset | ApplicationState::SoftReset => ApplicationState::Running,
+        application_state => application_state,
+    }
+}
+
+fn keys_to_mask(keyboard: &HashMap<u8, bool>) -> u16 {
+    (0..16u8).filter(|key| *keyboard.get(key).unwrap_or(&false)).fold(0, |mask, key| mask | 1 << key)
+}
+
+// Input only changes on frame boundaries, both while recording and playing back,
+// so the emulator sees exactly the same keys on every cycle.
+struct FrameInput {
+    keyboard: HashMap<u8, bool>,
+    mask: u16,
+    frame: u64,
+    latch: bool,
+}
+
+impl FrameInput {
+    fn new() -> Self {
+        FrameInput { keyboard: (0..16).map(|key| (key, false)).collect(), mask: 0, frame: 0, latch: true }
+    }
+
+    fn set(&mut self, mask: u16) -> Option<u8> {
+        let pressed = mask & !self.mask;
+
+        self.mask = mask;
+        for key in 0..16u8 {
+            self.keyboard.insert(key, mask & 1 << key != 0);
+        }
+
+        if pressed != 0 {
+            Some(pressed.trailing_zeros() as u8)
+        } else {
+            None
+        }
+    }
+
+    fn observe(&mut self, state: &State) {
+        if state.frame != self.frame {
+            self.frame = state.frame;
+            self.latch = true;
+        }
+    }
+}
+
+pub struct Recorder<E> {
+    inner: E,
+    movie: Movie,
+    input: FrameInput,
+}
+
+impl<E> Recorder<E> {
+    pub fn new(inner: E, movie: Movie) -> Self {
+        Recorder { inner, movie, input: FrameInput::new() }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+impl<E: KeyboardHandler> KeyboardHandler for Recorder<E> {
+    fn handle_keyboard(&mut self) -> (&HashMap<u8, bool>, Option<u8>, ApplicationState) {
+        let (keyboard, _, application_state) = self.inner.handle_keyboard();
+        let application_state = without_reset(application_state);
+        let mask = keys_to_mask(keyboard);
+
+        if let ApplicationState::Stopping = application_state {
+            return (&self.input.keyboard, None, application_state);
+        }
+
+        let keydown = if self.input.latch {
+            self.input.latch = false;
+            self.movie.frames.push(mask);
+            self.input.set(mask)
+        } else {
+            None
+        };
+
+        (&self.input.keyboard, keydown, application_state)
+    }
+}
+
+impl<E: StateHandler> StateHandler for Recorder<E> {
+    fn handle_state(&mut self, state: State) {
+        self.input.observe(&state);
+        self.inner.handle_state(state);
+    }
+
+    fn handle_speed(&mut self, speed: Speed) {
+        self.inner.handle_speed(speed);
+    }
+
+    fn handle_machine(&mut self, chip8: &Chip8) {
+        self.inner.handle_machine(chip8);
+    }
+}
+
+/// Feeds a recorded movie back to the emulator, and stops once it runs out of frames.
+pub struct Player<E> {
+    inner: E,
+    movie: Movie,
+    input: FrameInput,
+    next_frame: usize,
+}
+
+impl<E> Player<E> {
+    pub fn new(inner: E, movie: Movie) -> Self {
+        Player { inner, movie, input: FrameInput::new(), next_frame: 0 }
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+impl<E: KeyboardHandler> KeyboardHandler for Player<E> {
+    fn handle_keyboard(&mut self) -> (&HashMap<u8, bool>, Option<u8>, ApplicationState) {
+        let (_, _, application_state) = self.inner.handle_keyboard();
+        let mut application_state = without_reset(application_state);
+
+        let keydown = if self.input.latch {
+            self.input.latch = false;
+
+            match self.movie.frames.get(self.next_frame) {
+                Some(mask) => {
+                    self.next_frame += 1;
+                    self.input.set(*mask)
+                },
+                None => {
+                    application_state = ApplicationState::Stopping;
+                    None
+                },
+            }
+        } else {
+            None
+        };
+
+        (&self.input.keyboard, keydown, application_state)
+    }
+}
+
+impl<E: StateHandler> StateHandler for Player<E> {
+    fn handle_state(&mut self, state: State) {
+        self.input.observe(&state);
+        self.inner.handle_state(state);
+    }
+
+    fn handle_speed(&mut self, speed: Speed) {
+        self.inner.handle_speed(speed);
+    }
+
+    fn handle_machine(&mut self, chip8: &Chip8) {
+        self.inner.handle_machine(chip8);
+    }
+}
+
+#[cfg(test)]
+struct TestKeys {
+    calls: u64,
+    keyboard: HashMap<u8, bool>,
+}
+
+#[cfg(test)]
+impl KeyboardHandler for TestKeys {
+    fn handle_keyboard(&mut self) -> (&HashMap<u8, bool>, Option<u8>, ApplicationState) {
+        self.calls += 1;
+        self.keyboard.insert(5, (30..90).contains(&self.calls));
+
+        let application_state = if self.calls > 200 { ApplicationState::Stopping } else { ApplicationState::Running };
+        (&self.keyboard, None, application_state)
+    }
+}
+
+#[cfg(test)]
+impl StateHandler for TestKeys {
+    fn handle_state(&mut self, _state: State) {}
+}
+
+#[cfg(test)]
+fn run_session<E: KeyboardHandler + StateHandler>(chip8: &mut crate::chip8::Chip8, engine: &mut E) {
+    loop {
+        let (keyboard, keydown, application_state) = engine.handle_keyboard();
+        if let ApplicationState::Stopping = application_state {
+            break;
+        }
+
+        let state = chip8.step(keyboard, keydown).unwrap();
+        engine.handle_state(state);
+    }
+}
+
+#[test]
+fn test_movie_roundtrip() {
+    let movie = Movie { rom_hash: 0xdeadbeef, seed: 7, cycles_per_frame: 20, quirks: Quirks::default(), frames: vec!(0, 0x20, 0x8001) };
+    assert_eq!(movie, Movie::parse(&movie.to_string()).unwrap());
+
+    let movie = Movie { quirks: Quirks { shift_uses_vy: true, clip_sprites: true, ..Quirks::default() }, ..movie };
+    assert_eq!(movie, Movie::parse(&movie.to_string()).unwrap());
+    assert!(Movie::parse("something else").is_err());
+}
+
+#[test]
+fn test_record_and_replay() {
+    use crate::chip8::{Chip8, Clock};
+
+    // LD V0, 0x05; RND V1, 0xFF; SKP V0; JP 0x202; ADD V2, 0x01; JP 0x202
+    let rom = vec!(0x60, 0x05, 0xC1, 0xFF, 0xE0, 0x9E, 0x12, 0x02, 0x72, 0x01, 0x12, 0x02);
+    let new_chip8 = |seed: u64| {
+        Chip8::builder().program(rom.clone()).seed(seed).clock(Clock::Cycles(5)).build().unwrap()
+    };
+
+    let mut recorded = new_chip8(42);
+    let mut recorder = Recorder::new(TestKeys { calls: 0, keyboard: HashMap::new() }, Movie::new(&rom, 42, 5, Quirks::default()));
+    run_session(&mut recorded, &mut recorder);
+
+    let movie = Movie::parse(&recorder.movie().to_string()).unwrap();
+    assert_eq!(rom_hash(&rom), movie.rom_hash);
+    assert!(movie.frames.contains(&0x20));
+
+    let mut replayed = new_chip8(movie.seed);
+    let mut player = Player::new(crate::headless::HeadlessEngine::new(None), movie);
+    run_session(&mut replayed, &mut player);
+
+    assert!(recorded.v[2] > 0);
+    assert_eq!(recorded.v, replayed.v);
+    assert_eq!(recorded.pc, replayed.pc);
+    assert_eq!(recorded.frame(), replayed.frame());
+}
+
+#[cfg(test)]
+struct ResetKeys(HashMap<u8, bool>);
+
+#[cfg(test)]
+impl KeyboardHandler for ResetKeys {
+    fn handle_keyboard(&mut self) -> (&HashMap<u8, bool>, Option<u8>, ApplicationState) {
+        (&self.0, None, ApplicationState::Reset)
+    }
+}
+
+#[test]
+fn test_resets_are_ignored() {
+    let mut recorder = Recorder::new(ResetKeys(HashMap::new()), Movie::new(&[], 1, 5, Quirks::default()));
+    assert!(matches!(recorder.handle_keyboard().2, ApplicationState::Running));
+
+    let mut player = Player::new(ResetKeys(HashMap::new()), recorder.movie().clone());
+    assert!(matches!(player.handle_keyboard().2, ApplicationState::Running));
+}
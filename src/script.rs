@@ -0,0 +1,205 @@
+use crate::{ApplicationState, KeyboardHandler, StateHandler};
+use crate::chip8::{Chip8, State};
+use crate::speed::Speed;
+
+use std::collections::HashMap;
+
+/// One step of an input script. Durations are in frames.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Command {
+    Wait(u64),
+    Press(u8, u64),
+    Hold(u8),
+    Release(u8),
+    WaitUntilDraw,
+    Quit,
+}
+
+enum Waiting {
+    Nothing,
+    Frame(u64),
+    Release(u8, u64),
+    Draw,
+}
+
+/// Parses a script such as `wait 30; press 5 for 3; wait_until_draw; press A`.
+/// Commands are separated by `;` or newlines, and `#` starts a comment.
+pub fn parse(script: &str) -> Result<Vec<Command>, String> {
+    script.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split(';'))
+        .map(|command| command.split_whitespace().collect::<Vec<&str>>())
+        .filter(|words| !words.is_empty())
+        .map(|words| parse_command(&words))
+        .collect()
+}
+
+fn parse_command(words: &[&str]) -> Result<Command, String> {
+    let key = |word: &str| match u8::from_str_radix(word, 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => Err(format!("invalid key {}", word)),
+    };
+    let frames = |word: &str| word.parse::<u64>().map_err(|_| format!("invalid frame count {}", word));
+
+    match words {
+        ["wait", count] => Ok(Command::Wait(frames(count)?)),
+        ["press", k] => Ok(Command::Press(key(k)?, 1)),
+        ["press", k, "for", count] => Ok(Command::Press(key(k)?, frames(count)?)),
+        ["hold", k] => Ok(Command::Hold(key(k)?)),
+        ["release", k] => Ok(Command::Release(key(k)?)),
+        ["wait_until_draw"] => Ok(Command::WaitUntilDraw),
+        ["quit"] => Ok(Command::Quit),
+        _ => Err(format!("unknown command: {}", words.join(" "))),
+    }
+}
+
+/// A keyboard driven by a script instead of a person. It wraps another engine,
+/// which still receives every state and can still stop the run.
+pub struct ScriptedKeyboard<E> {
+    inner: E,
+    commands: Vec<Command>,
+    next: usize,
+    keyboard: HashMap<u8, bool>,
+    waiting: Waiting,
+    frame: u64,
+    drawn: bool,
+}
+
+impl<E> ScriptedKeyboard<E> {
+    pub fn new(inner: E, script: &str) -> Result<Self, String> {
+        Ok(ScriptedKeyboard {
+            inner,
+            commands: parse(script)?,
+            next: 0,
+            keyboard: (0..16).map(|key| (key, false)).collect(),
+            waiting: Waiting::Nothing,
+            frame: 0,
+            drawn: false,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next == self.commands.len()
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    fn advance(&mut self) -> (Option<u8>, ApplicationState) {
+        let mut keydown = None;
+
+        loop {
+            match self.waiting {
+                Waiting::Nothing => {},
+                Waiting::Frame(frame) if self.frame >= frame => {},
+                Waiting::Release(key, frame) if self.frame >= frame => {
+                    self.keyboard.insert(key, false);
+                },
+                Waiting::Draw if self.drawn => {},
+                _ => return (keydown, ApplicationState::Running),
+            }
+            self.waiting = Waiting::Nothing;
+
+            let command = match self.commands.get(self.next) {
+                Some(command) => *command,
+                None => return (keydown, ApplicationState::Running),
+            };
+            self.next += 1;
+
+            match command {
+                Command::Wait(frames) => self.waiting = Waiting::Frame(self.frame + frames),
+                Command::Press(key, frames) => {
+                    self.keyboard.insert(key, true);
+                    keydown = Some(key);
+                    self.waiting = Waiting::Release(key, self.frame + frames);
+                },
+                Command::Hold(key) => {
+                    self.keyboard.insert(key, true);
+                    keydown = Some(key);
+                },
+                Command::Release(key) => {
+                    self.keyboard.insert(key, false);
+                },
+                Command::WaitUntilDraw => {
+                    self.drawn = false;
+                    self.waiting = Waiting::Draw;
+                },
+                Command::Quit => return (keydown, ApplicationState::Stopping),
+            }
+        }
+    }
+}
+
+impl<E: KeyboardHandler> KeyboardHandler for ScriptedKeyboard<E> {
+    fn handle_keyboard(&mut self) -> (&HashMap<u8, bool>, Option<u8>, ApplicationState) {
+        let (_, _, application_state) = self.inner.handle_keyboard();
+        let (keydown, script_state) = self.advance();
+
+        let application_state = match application_state {
+            ApplicationState::Running => script_state,
+            other => other,
+        };
+
+        (&self.keyboard, keydown, application_state)
+    }
+}
+
+impl<E: StateHandler> StateHandler for ScriptedKeyboard<E> {
+    fn handle_state(&mut self, state: State) {
+        self.frame = state.frame;
+        if state.update_display {
+            self.drawn = true;
+        }
+
+        self.inner.handle_state(state);
+    }
+
+    fn handle_speed(&mut self, speed: Speed) {
+        self.inner.handle_speed(speed);
+    }
+
+    fn handle_machine(&mut self, chip8: &Chip8) {
+        self.inner.handle_machine(chip8);
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        Ok(vec!(Command::Wait(30), Command::Press(5, 3), Command::WaitUntilDraw, Command::Press(0xA, 1))),
+        parse("wait 30; press 5 for 3; wait_until_draw; press A"));
+    assert_eq!(
+        Ok(vec!(Command::Hold(0), Command::Release(0), Command::Quit)),
+        parse("hold 0 # comment\nrelease 0\n\nquit"));
+    assert!(parse("press G").is_err());
+    assert!(parse("jump").is_err());
+}
+
+#[test]
+fn test_scripted_run() {
+    use crate::chip8::{Chip8, Clock};
+    use crate::headless::HeadlessEngine;
+
+    // LD V0, K; LD V1, 0x00; SKNP V0; ADD V1, 0x01; JP 0x204
+    let program = vec!(0xF0, 0x0A, 0x61, 0x00, 0xE0, 0xA1, 0x71, 0x01, 0x12, 0x04);
+    let mut chip8 = Chip8::builder().program(program).clock(Clock::Cycles(10)).build().unwrap();
+
+    let mut engine = ScriptedKeyboard::new(HeadlessEngine::new(None), "wait 2; press 7 for 3; wait 1; quit").unwrap();
+
+    loop {
+        let (keyboard, keydown, application_state) = engine.handle_keyboard();
+        if let ApplicationState::Stopping = application_state {
+            break;
+        }
+
+        let state = chip8.step(keyboard, keydown).unwrap();
+        engine.handle_state(state);
+    }
+
+    assert!(engine.is_finished());
+    assert_eq!(7, chip8.v[0]);
+    assert_eq!(6, chip8.frame());
+    // Three frames of ten cycles with the key held, counted once per three-instruction loop
+    assert_eq!(10, chip8.v[1]);
+}
@@ -1,70 +1,80 @@
 extern crate sdl2;
 
-use crate::{ApplicationState, KeyboardHandler, StateHandler};
+use crate::{ApplicationState, AudioSink, KeyboardHandler, StateHandler};
+use crate::speed::{Control, Speed};
+use crate::bitmap_font;
+use crate::chip8::Chip8;
+use crate::osd::{Osd, OsdMode};
+use crate::debug_view::{self, DebugPanels};
 
 use sdl2::pixels::Color;
+use sdl2::render::BlendMode;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::rect::Rect;
-use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 
 use std::collections::HashMap;
+use std::io;
 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32
+// Frames of audio queued at most, so fast-forward does not build up latency
+const MAX_QUEUED_FRAMES: u32 = 4;
+
+/// Plays the beeper through the default audio device.
+pub struct SdlAudio {
+    device: AudioQueue<f32>,
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
-
-    fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+impl AudioSink for SdlAudio {
+    fn sample_rate(&self) -> u32 {
+        self.device.spec().freq as u32
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let frame_size = std::mem::size_of_val(samples) as u32;
+
+        if self.device.size() < MAX_QUEUED_FRAMES * frame_size {
+            self.device.queue(samples);
         }
+
+        Ok(())
     }
 }
 
 pub struct SdlEngine {
-    device: sdl2::audio::AudioDevice<SquareWave>,
+    audio_subsystem: sdl2::AudioSubsystem,
     canvas: sdl2::render::Canvas<sdl2::video::Window>,
     event_pump: sdl2::EventPump,
     keyboard: HashMap<u8, bool>,
+    display: [u8; 64*32],
+    osd: Osd,
+    speed: Speed,
+    debug_view: bool,
+    panels: DebugPanels,
+    // Set when a render waits for the machine state of the debug panels
+    render_pending: bool,
 }
 
+const GAME_WIDTH: u32 = 800;
+const GAME_HEIGHT: u32 = 400;
+// Extra window width taken by the debug panels
+const DEBUG_WIDTH: u32 = 480;
+// Size of one pixel of the on-screen display font
+const OSD_SCALE: usize = 2;
+// Size of one pixel of the sprite preview
+const SPRITE_SCALE: usize = 6;
+
 impl SdlEngine {
     pub fn new() -> Self {
         let sdl_context = sdl2::init().unwrap();
 
         let video_subsystem = sdl_context.video().unwrap();
-        let window = video_subsystem.window("chip8", 800, 400)
+        let window = video_subsystem.window("chip8", GAME_WIDTH, GAME_HEIGHT)
             .position_centered()
             .build().unwrap();
 
         let audio_subsystem = sdl_context.audio().unwrap();
 
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),  // mono
-            samples: None       // default sample size
-        };
-
-        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            // initialize the audio callback
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25
-            }
-        }).unwrap();
-
         let mut canvas = window.into_canvas().build().unwrap();
         let bg_color = Color::RGB(0, 0, 0);
         canvas.set_draw_color(bg_color);
@@ -91,46 +101,225 @@ impl SdlEngine {
         keyboard.insert(0xB, false);
         keyboard.insert(0xF, false);
 
-        SdlEngine { device, canvas, event_pump, keyboard }
+        SdlEngine {
+            audio_subsystem,
+            canvas,
+            event_pump,
+            keyboard,
+            display: [0; 64*32],
+            osd: Osd::new(OsdMode::Off),
+            speed: Speed::Normal,
+            debug_view: false,
+            panels: DebugPanels::default(),
+            render_pending: false,
+        }
     }
-}
 
-impl StateHandler for SdlEngine {
-    fn handle_state(&mut self, state: crate::chip8::State) { 
+    pub fn open_audio(&self) -> Result<SdlAudio, String> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),  // mono
+            samples: None       // default sample size
+        };
+
+        let device = self.audio_subsystem.open_queue::<f32, _>(None, &desired_spec)?;
+        device.resume();
+
+        Ok(SdlAudio { device })
+    }
+
+    pub fn set_osd(&mut self, mode: OsdMode) {
+        self.osd.set_mode(mode);
+        self.render();
+    }
+
+    /// Shows disassembly, registers, the stack and memory at I next to the game.
+    pub fn set_debug_view(&mut self, enabled: bool) {
+        self.debug_view = enabled;
+
+        let width = if enabled { GAME_WIDTH + DEBUG_WIDTH } else { GAME_WIDTH };
+        self.canvas.window_mut().set_size(width, GAME_HEIGHT).unwrap();
+        self.render();
+    }
+
+    fn render(&mut self) {
         let bg_color = Color::RGB(0, 0, 0);
         let fg_color = Color::RGB(255, 255, 255);
 
-        if state.update_display {
-            self.canvas.set_draw_color(bg_color);
-            self.canvas.clear();
-            self.canvas.set_draw_color(fg_color);
-
-            let mut rects = vec!();
-            
-            for y in 0..32 {
-                for x in 0..64 {
-                    if state.display[y*64+x] > 0 {
-                        rects.push(Rect::new((x * (800/64)) as i32, (y * (400/32))as i32, 800/64 - 1, 400/32 - 1));
-                    }
+        self.canvas.set_draw_color(bg_color);
+        self.canvas.clear();
+        self.canvas.set_draw_color(fg_color);
+
+        let mut rects = vec!();
+        
+        for y in 0..32 {
+            for x in 0..64 {
+                if self.display[y*64+x] > 0 {
+                    rects.push(Rect::new((x * (800/64)) as i32, (y * (400/32))as i32, 800/64 - 1, 400/32 - 1));
                 }
             }
+        }
+
+        self.canvas.fill_rects(&rects).unwrap();
+
+        let lines = self.osd.lines(&self.keyboard);
+        if !lines.is_empty() {
+            self.draw_text_box(4, 4, &lines);
+        }
 
-            self.canvas.fill_rects(&rects).unwrap();
-            self.canvas.present();
+        if self.debug_view {
+            self.render_panels();
         }
 
-        if state.play_audio {
-            self.device.resume();
-        } else {
-            self.device.pause();
+        self.canvas.present();
+    }
+
+    fn render_panels(&mut self) {
+        let left = GAME_WIDTH as usize + 4;
+        let right = left + DEBUG_WIDTH as usize / 2;
+        let line_height = (bitmap_font::HEIGHT + 2) * OSD_SCALE;
+        let panel_height = |lines: &[String]| lines.len() * line_height + 2 * OSD_SCALE + 4;
+
+        let panels = self.panels.clone();
+
+        self.draw_text_box(left, 4, &panels.disassembly);
+        self.draw_text_box(left, 4 + panel_height(&panels.disassembly), &panels.stack);
+
+        let mut top = 4;
+        self.draw_text_box(right, top, &panels.registers);
+        top += panel_height(&panels.registers);
+        self.draw_text_box(right, top, &panels.memory);
+        top += panel_height(&panels.memory);
+
+        let title = vec!(format!("SPRITE AT I {:04X}", panels.sprite_address));
+        self.draw_text_box(right, top, &title);
+        top += panel_height(&title);
+
+        self.canvas.set_draw_color(Color::RGB(40, 40, 40));
+        self.canvas.fill_rect(Rect::new(right as i32, top as i32, (8 * SPRITE_SCALE) as u32, (debug_view::SPRITE_ROWS * SPRITE_SCALE) as u32)).unwrap();
+        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
+
+        let mut rects = vec!();
+        for (y, row) in panels.sprite.iter().enumerate() {
+            for x in 0..8 {
+                if row & (0x80 >> x) != 0 {
+                    rects.push(Rect::new((right + x * SPRITE_SCALE) as i32, (top + y * SPRITE_SCALE) as i32, SPRITE_SCALE as u32 - 1, SPRITE_SCALE as u32 - 1));
+                }
+            }
+        }
+
+        self.canvas.fill_rects(&rects).unwrap();
+    }
+
+    fn draw_text_box(&mut self, x: usize, y: usize, lines: &[String]) {
+        let line_height = (bitmap_font::HEIGHT + 2) * OSD_SCALE;
+        let width = lines.iter().map(|line| bitmap_font::text_width(line)).max().unwrap_or_default() * OSD_SCALE;
+
+        self.canvas.set_blend_mode(BlendMode::Blend);
+        self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 192));
+        self.canvas.fill_rect(Rect::new(x as i32, y as i32, (width + 2 * OSD_SCALE) as u32, (lines.len() * line_height + OSD_SCALE) as u32)).unwrap();
+        self.canvas.set_blend_mode(BlendMode::None);
+
+        self.canvas.set_draw_color(Color::RGB(0, 255, 0));
+        let mut rects = vec!();
+
+        for (row, line) in lines.iter().enumerate() {
+            for (px, py) in bitmap_font::pixels(line) {
+                let left = x + OSD_SCALE + px * OSD_SCALE;
+                let top = y + OSD_SCALE + row * line_height + py * OSD_SCALE;
+                rects.push(Rect::new(left as i32, top as i32, OSD_SCALE as u32, OSD_SCALE as u32));
+            }
+        }
+
+        self.canvas.fill_rects(&rects).unwrap();
+    }
+}
+
+impl Default for SdlEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateHandler for SdlEngine {
+    fn handle_state(&mut self, state: crate::chip8::State) { 
+        let new_frame = self.osd.record_step(state.frame);
+
+        if state.update_display {
+            self.display = state.display;
+        }
+
+        // The overlay changes without the game drawing, so refresh it once a frame
+        let render = state.update_display || (new_frame && self.osd.mode() != OsdMode::Off);
+
+        if self.debug_view {
+            // A render still pending means handle_machine is not being called
+            if self.render_pending {
+                self.render();
+            }
+
+            // The panels are drawn from the machine after this instruction, which
+            // handle_machine receives next. Refresh them every step while paused.
+            self.render_pending = render || new_frame || self.speed == Speed::Paused;
+        } else if render {
+            self.render();
         }
      }
+
+    fn handle_speed(&mut self, speed: Speed) {
+        let title = match speed {
+            Speed::Normal => "chip8".to_string(),
+            speed => format!("chip8 - {}", speed),
+        };
+        self.canvas.window_mut().set_title(&title).unwrap();
+
+        self.speed = speed;
+        self.osd.set_speed(speed);
+        self.render();
+    }
+
+    fn handle_machine(&mut self, chip8: &Chip8) {
+        self.osd.record_machine(chip8);
+
+        if self.debug_view {
+            self.panels = DebugPanels::capture(chip8);
+
+            if self.render_pending {
+                self.render_pending = false;
+                self.render();
+            }
+        }
+    }
+}
+
+fn keypad(keycode: Option<Keycode>) -> Option<u8> {
+    match keycode {
+        Some(Keycode::Num1) => Some(1),
+        Some(Keycode::Num2) => Some(2),
+        Some(Keycode::Num3) => Some(3),
+        Some(Keycode::Num4) => Some(0xC),
+        Some(Keycode::Q) => Some(4),
+        Some(Keycode::W) => Some(5),
+        Some(Keycode::E) => Some(6),
+        Some(Keycode::R) => Some(0xD),
+        Some(Keycode::A) => Some(7),
+        Some(Keycode::S) => Some(8),
+        Some(Keycode::D) => Some(9),
+        Some(Keycode::F) => Some(0xE),
+        Some(Keycode::Z) => Some(0xA),
+        Some(Keycode::X) => Some(0),
+        Some(Keycode::C) => Some(0xB),
+        Some(Keycode::V) => Some(0xF),
+        _ => None,
+    }
 }
 
 impl KeyboardHandler for SdlEngine {
     fn handle_keyboard(&mut self) -> (&std::collections::HashMap<u8, bool>, std::option::Option<u8>, ApplicationState) { 
         let mut keydown = None;
         let mut application_state = ApplicationState::Running;
+        let mut redraw = false;
+        let mut resize = false;
        
         for event in self.event_pump.poll_iter() {
             match event {
@@ -138,25 +327,48 @@ impl KeyboardHandler for SdlEngine {
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     application_state = ApplicationState::Stopping;
                 },
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => {
+                    let mode = self.osd.mode().next();
+                    self.osd.set_mode(mode);
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } => {
+                    self.debug_view = !self.debug_view;
+                    resize = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+                    application_state = ApplicationState::Reset;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    application_state = ApplicationState::SoftReset;
+                },
+                Event::KeyDown { keycode: Some(Keycode::Space), repeat: false, .. } => {
+                    application_state = ApplicationState::Speed(Control::TogglePause);
+                },
+                Event::KeyDown { keycode: Some(Keycode::N), .. } => {
+                    application_state = ApplicationState::Speed(Control::FrameAdvance);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => {
+                    application_state = ApplicationState::ToggleClip;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F10), .. } => {
+                    application_state = ApplicationState::Speed(Control::Step);
+                },
+                Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } => {
+                    application_state = ApplicationState::Speed(Control::ToggleSlowMotion);
+                },
+                Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+                    application_state = ApplicationState::Speed(Control::FastForward(true));
+                },
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } => {
+                    application_state = ApplicationState::Speed(Control::FastForward(false));
+                },
+                // Holding a toggle down only fires it once
+                Event::KeyDown { keycode: Some(Keycode::Space | Keycode::M | Keycode::Tab | Keycode::F5 | Keycode::F6 | Keycode::F1 | Keycode::F2 | Keycode::F9), repeat: true, .. } => {},
                 Event::KeyDown { keycode, .. } => {
-                    let key = match keycode {
-                        Some(Keycode::Num1) => 1,
-                        Some(Keycode::Num2) => 2,
-                        Some(Keycode::Num3) => 3,
-                        Some(Keycode::Num4) => 0xC,
-                        Some(Keycode::Q) => 4,
-                        Some(Keycode::W) => 5,
-                        Some(Keycode::E) => 6,
-                        Some(Keycode::R) => 0xD,
-                        Some(Keycode::A) => 7,
-                        Some(Keycode::S) => 8,
-                        Some(Keycode::D) => 9,
-                        Some(Keycode::F) => 0xE,
-                        Some(Keycode::Z) => 0xA,
-                        Some(Keycode::X) => 0,
-                        Some(Keycode::C) => 0xB,
-                        Some(Keycode::V) => 0xF,
-                        _ => 0
+                    let key = match keypad(keycode) {
+                        Some(key) => key,
+                        None => continue,
                     };
 
                     keydown = Some(key);
@@ -164,32 +376,20 @@ impl KeyboardHandler for SdlEngine {
                     self.keyboard.insert(key, true);
                 },
                 Event::KeyUp { keycode, .. } => {
-                    let key = match keycode {
-                        Some(Keycode::Num1) => 1,
-                        Some(Keycode::Num2) => 2,
-                        Some(Keycode::Num3) => 3,
-                        Some(Keycode::Num4) => 0xC,
-                        Some(Keycode::Q) => 4,
-                        Some(Keycode::W) => 5,
-                        Some(Keycode::E) => 6,
-                        Some(Keycode::R) => 0xD,
-                        Some(Keycode::A) => 7,
-                        Some(Keycode::S) => 8,
-                        Some(Keycode::D) => 9,
-                        Some(Keycode::F) => 0xE,
-                        Some(Keycode::Z) => 0xA,
-                        Some(Keycode::X) => 0,
-                        Some(Keycode::C) => 0xB,
-                        Some(Keycode::V) => 0xF,
-                        _ => 0
-                    };
-                   
-                    self.keyboard.insert(key, false);
+                    if let Some(key) = keypad(keycode) {
+                        self.keyboard.insert(key, false);
+                    }
                 },
                 _ => {}
             }
         }
 
+        if resize {
+            self.set_debug_view(self.debug_view);
+        } else if redraw {
+            self.render();
+        }
+
         (&self.keyboard, keydown, application_state)
      }
 }
\ No newline at end of file
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FRAMES_PER_SECOND: u32 = 60;
+// How far the scheduler may fall behind before it gives up catching up
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// How fast emulated frames are run compared to real time.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Speed {
+    Normal,
+    Paused,
+    /// Faster by the given factor, or as fast as possible.
+    FastForward(Option<u32>),
+    /// Slower by the given factor.
+    SlowMotion(u32),
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Speed::Normal => write!(fmt, "running"),
+            Speed::Paused => write!(fmt, "paused"),
+            Speed::FastForward(None) => write!(fmt, "fast forward"),
+            Speed::FastForward(Some(factor)) => write!(fmt, "fast forward {}x", factor),
+            Speed::SlowMotion(factor) => write!(fmt, "slow motion 1/{}x", factor),
+        }
+    }
+}
+
+/// Speed changes requested by a frontend.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Control {
+    TogglePause,
+    /// Runs a single frame while paused, and pauses otherwise.
+    FrameAdvance,
+    /// Runs a single instruction while paused, and pauses otherwise.
+    Step,
+    /// Fast-forward while held: `true` on press, `false` on release.
+    FastForward(bool),
+    ToggleSlowMotion,
+}
+
+/// Paces emulated frames against the wall clock.
+pub struct Scheduler {
+    fast_forward_factor: Option<u32>,
+    slow_motion_factor: u32,
+    paused: bool,
+    fast_forward: bool,
+    slow_motion: bool,
+    advance: bool,
+    step: bool,
+    next_frame: Instant,
+}
+
+impl Scheduler {
+    pub fn new(fast_forward_factor: Option<u32>, slow_motion_factor: u32) -> Self {
+        Scheduler {
+            fast_forward_factor,
+            slow_motion_factor,
+            paused: false,
+            fast_forward: false,
+            slow_motion: false,
+            advance: false,
+            step: false,
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        if self.paused {
+            Speed::Paused
+        } else if self.fast_forward {
+            Speed::FastForward(self.fast_forward_factor)
+        } else if self.slow_motion {
+            Speed::SlowMotion(self.slow_motion_factor)
+        } else {
+            Speed::Normal
+        }
+    }
+
+    pub fn control(&mut self, control: Control) {
+        match control {
+            Control::TogglePause => self.paused = !self.paused,
+            Control::FrameAdvance if self.paused => self.advance = true,
+            Control::FrameAdvance => self.paused = true,
+            Control::Step if self.paused => self.step = true,
+            Control::Step => self.paused = true,
+            Control::FastForward(held) => self.fast_forward = held,
+            Control::ToggleSlowMotion => self.slow_motion = !self.slow_motion,
+        }
+
+        self.next_frame = Instant::now();
+    }
+
+    /// Whether the emulator should execute instructions right now.
+    pub fn should_run(&self) -> bool {
+        !self.paused || self.advance || self.step
+    }
+
+    /// Called after every executed instruction.
+    pub fn end_step(&mut self) {
+        self.step = false;
+    }
+
+    /// Waits while paused, so that polling for input does not spin.
+    pub fn idle(&mut self) {
+        thread::sleep(Duration::from_secs(1) / FRAMES_PER_SECOND);
+        self.next_frame = Instant::now();
+    }
+
+    /// Called after every emulated frame. Sleeps until the next frame is due.
+    pub fn end_frame(&mut self) {
+        self.advance = false;
+
+        let now = Instant::now();
+        match self.frame_duration() {
+            Some(duration) => {
+                self.next_frame += duration;
+
+                if self.next_frame > now {
+                    thread::sleep(self.next_frame - now);
+                } else if now - self.next_frame > MAX_LAG {
+                    self.next_frame = now;
+                }
+            },
+            None => self.next_frame = now,
+        }
+    }
+
+    fn frame_duration(&self) -> Option<Duration> {
+        let frame = Duration::from_secs(1) / FRAMES_PER_SECOND;
+
+        match self.speed() {
+            Speed::Normal | Speed::Paused => Some(frame),
+            Speed::FastForward(Some(factor)) => Some(frame / factor.max(1)),
+            Speed::FastForward(None) => None,
+            Speed::SlowMotion(factor) => Some(frame * factor),
+        }
+    }
+}
+
+#[test]
+fn test_controls() {
+    let mut scheduler = Scheduler::new(None, 4);
+    assert_eq!(Speed::Normal, scheduler.speed());
+
+    scheduler.control(Control::FastForward(true));
+    assert_eq!((Speed::FastForward(None), None), (scheduler.speed(), scheduler.frame_duration()));
+    scheduler.control(Control::FastForward(false));
+
+    scheduler.control(Control::ToggleSlowMotion);
+    assert_eq!(Speed::SlowMotion(4), scheduler.speed());
+    assert_eq!(Some(Duration::from_secs(1) / 60 * 4), scheduler.frame_duration());
+
+    // Frame advance pauses first, then runs one frame per press
+    scheduler.control(Control::FrameAdvance);
+    assert_eq!(Speed::Paused, scheduler.speed());
+    assert!(!scheduler.should_run());
+
+    scheduler.control(Control::FrameAdvance);
+    assert!(scheduler.should_run());
+    scheduler.end_frame();
+    assert!(!scheduler.should_run());
+
+    scheduler.control(Control::Step);
+    assert!(scheduler.should_run());
+    scheduler.end_step();
+    assert!(!scheduler.should_run());
+
+    scheduler.control(Control::TogglePause);
+    assert_eq!(Speed::SlowMotion(4), scheduler.speed());
+    assert_eq!("slow motion 1/4x", scheduler.speed().to_string());
+}
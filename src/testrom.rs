@@ -0,0 +1,312 @@
+//! Self-checking test ROMs. Each program exercises one instruction behaviour,
+//! checks the result with SE/SNE and finishes by clearing the screen and
+//! drawing a pass or fail glyph, so it can be run on any CHIP-8 interpreter.
+
+use crate::chip8::{Chip8, Clock};
+
+use std::collections::HashMap;
+
+const START: u16 = 0x200;
+const PASS_GLYPH: u16 = 0x202;
+const FAIL_GLYPH: u16 = 0x207;
+const PASS: u16 = 0x20C;
+const FAIL: u16 = 0x218;
+const BODY: u16 = 0x224;
+
+/// Where the verdict glyph is drawn.
+pub const GLYPH_X: usize = 28;
+pub const GLYPH_Y: usize = 13;
+
+pub const PASS_SPRITE: [u8; 5] = [0b0000_0001, 0b0000_0010, 0b1000_0100, 0b0100_1000, 0b0011_0000];
+pub const FAIL_SPRITE: [u8; 5] = [0b1000_1000, 0b0101_0000, 0b0010_0000, 0b0101_0000, 0b1000_1000];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Verdict {
+    Pass,
+    Fail,
+    /// The program never drew a verdict, or the screen shows something else.
+    Unknown,
+}
+
+/// Builds a test program. The pass and fail routines live at fixed addresses
+/// in front of the body, so checks can jump to them without patching.
+pub struct Program {
+    words: Vec<u16>,
+}
+
+impl Program {
+    pub fn new() -> Self {
+        let mut program = Program { words: vec!() };
+
+        program.op(0x1000 | BODY);
+        program.data(&[PASS_SPRITE, FAIL_SPRITE].concat());
+
+        for glyph in [PASS_GLYPH, FAIL_GLYPH].iter() {
+            // CLS; LD I, glyph; LD V0, x; LD V1, y; DRW V0, V1, 5; JP self
+            let here = program.here();
+            program.op(0x00E0);
+            program.op(0xA000 | glyph);
+            program.op(0x6000 | GLYPH_X as u16);
+            program.op(0x6100 | GLYPH_Y as u16);
+            program.op(0xD015);
+            program.op(0x1000 | (here + 10));
+        }
+
+        debug_assert_eq!(BODY, program.here());
+        program
+    }
+
+    pub fn here(&self) -> u16 {
+        START + self.words.len() as u16 * 2
+    }
+
+    pub fn op(&mut self, word: u16) -> &mut Self {
+        self.words.push(word);
+        self
+    }
+
+    fn data(&mut self, bytes: &[u8]) {
+        for pair in bytes.chunks(2) {
+            let low = pair.get(1).copied().unwrap_or_default();
+            self.words.push((pair[0] as u16) << 8 | low as u16);
+        }
+    }
+
+    /// Fails unless VX == byte.
+    pub fn expect(&mut self, vx: u8, byte: u8) -> &mut Self {
+        self.op(0x3000 | (vx as u16) << 8 | byte as u16).op(0x1000 | FAIL)
+    }
+
+    /// Fails if VX == byte.
+    pub fn expect_not(&mut self, vx: u8, byte: u8) -> &mut Self {
+        self.op(0x4000 | (vx as u16) << 8 | byte as u16).op(0x1000 | FAIL)
+    }
+
+    /// Fails unless the previous instruction skipped the next one.
+    pub fn expect_skip(&mut self) -> &mut Self {
+        self.op(0x1000 | FAIL)
+    }
+
+    /// Fails if the previous instruction skipped the next one.
+    pub fn expect_no_skip(&mut self) -> &mut Self {
+        let over = self.here() + 4;
+        self.op(0x1000 | over).op(0x1000 | FAIL)
+    }
+
+    pub fn pass(&mut self) -> &mut Self {
+        self.op(0x1000 | PASS)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.words.iter().flat_map(|word| vec!((word >> 8) as u8, *word as u8)).collect()
+    }
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Program::new()
+    }
+}
+
+pub struct TestRom {
+    pub name: &'static str,
+    pub rom: Vec<u8>,
+}
+
+fn test_rom(name: &'static str, body: impl Fn(&mut Program)) -> TestRom {
+    let mut program = Program::new();
+    body(&mut program);
+    program.pass();
+
+    TestRom { name, rom: program.to_bytes() }
+}
+
+/// One program per instruction behaviour.
+pub fn test_roms() -> Vec<TestRom> {
+    vec!(
+        test_rom("jp", |p| {
+            let target = p.here() + 4;
+            p.op(0x1000 | target).op(0x1000 | FAIL);
+        }),
+        test_rom("call-ret", |p| {
+            // JP over the subroutine, which sets V5 and returns
+            let subroutine = p.here() + 2;
+            p.op(0x1000 | (subroutine + 4));
+            p.op(0x6501).op(0x00EE);
+            p.op(0x2000 | subroutine).expect(5, 1);
+        }),
+        test_rom("se-byte", |p| {
+            p.op(0x6005).op(0x3005).expect_skip();
+            p.op(0x3006).expect_no_skip();
+        }),
+        test_rom("sne-byte", |p| {
+            p.op(0x6005).op(0x4006).expect_skip();
+            p.op(0x4005).expect_no_skip();
+        }),
+        test_rom("se-reg", |p| {
+            p.op(0x6005).op(0x6105).op(0x6206);
+            p.op(0x5010).expect_skip();
+            p.op(0x5020).expect_no_skip();
+        }),
+        test_rom("sne-reg", |p| {
+            p.op(0x6005).op(0x6105).op(0x6206);
+            p.op(0x9020).expect_skip();
+            p.op(0x9010).expect_no_skip();
+        }),
+        test_rom("ld", |p| {
+            p.op(0x632A).expect(3, 0x2A);
+            p.op(0x8430).expect(4, 0x2A);
+        }),
+        test_rom("add-byte", |p| {
+            // Wraps around without touching VF
+            p.op(0x6F07).op(0x60FF).op(0x7002).expect(0, 0x01).expect(0xF, 0x07);
+        }),
+        test_rom("or", |p| {
+            p.op(0x600C).op(0x610A).op(0x8011).expect(0, 0x0E).expect(1, 0x0A);
+        }),
+        test_rom("and", |p| {
+            p.op(0x600C).op(0x610A).op(0x8012).expect(0, 0x08);
+        }),
+        test_rom("xor", |p| {
+            p.op(0x600C).op(0x610A).op(0x8013).expect(0, 0x06);
+        }),
+        test_rom("add-carry", |p| {
+            p.op(0x60F0).op(0x610F).op(0x8014).expect(0, 0xFF).expect(0xF, 0);
+            p.op(0x8014).expect(0, 0x0E).expect(0xF, 1);
+        }),
+        test_rom("add-vf-destination", |p| {
+            p.op(0x6FFF).op(0x6102).op(0x8F14).expect(0xF, 1);
+        }),
+        test_rom("sub-borrow", |p| {
+            p.op(0x6005).op(0x6103).op(0x8015).expect(0, 0x02).expect(0xF, 1);
+            p.op(0x8015).expect(0, 0xFF).expect(0xF, 0);
+        }),
+        test_rom("sub-equal", |p| {
+            p.op(0x6005).op(0x6105).op(0x8015).expect(0, 0x00).expect(0xF, 1);
+        }),
+        test_rom("subn", |p| {
+            p.op(0x6003).op(0x6105).op(0x8017).expect(0, 0x02).expect(0xF, 1);
+            p.op(0x6005).op(0x6103).op(0x8017).expect(0, 0xFE).expect(0xF, 0);
+        }),
+        test_rom("shr", |p| {
+            p.op(0x6081).op(0x8006).expect(0, 0x40).expect(0xF, 1);
+            p.op(0x8006).expect(0, 0x20).expect(0xF, 0);
+        }),
+        test_rom("shl", |p| {
+            p.op(0x6081).op(0x800E).expect(0, 0x02).expect(0xF, 1);
+            p.op(0x800E).expect(0, 0x04).expect(0xF, 0);
+        }),
+        test_rom("ld-i-add-i", |p| {
+            // Store through I = 0x300 + 0x10, then read back through I = 0x310
+            p.op(0xA300).op(0x6210).op(0xF21E);
+            p.op(0x6055).op(0xF055).op(0x6000);
+            p.op(0xA310).op(0xF065).expect(0, 0x55);
+        }),
+        test_rom("jp-v0", |p| {
+            let target = p.here() + 8;
+            p.op(0x6004).op(0xB000 | (target - 4)).op(0x1000 | FAIL).op(0x1000 | FAIL);
+        }),
+        test_rom("rnd-mask", |p| {
+            p.op(0xC00F).op(0x61F0).op(0x8102).expect(1, 0);
+        }),
+        test_rom("drw-collision", |p| {
+            p.op(0x6000).op(0x6100).op(0xF029);
+            p.op(0xD015).expect(0xF, 0);
+            p.op(0xD015).expect(0xF, 1);
+        }),
+        test_rom("skp-sknp", |p| {
+            // No key is held while the test runs
+            p.op(0x6005).op(0xE0A1).expect_skip();
+            p.op(0xE09E).expect_no_skip();
+        }),
+        test_rom("timers", |p| {
+            p.op(0x603C).op(0xF015).op(0xF018).op(0xF107).expect_not(1, 0);
+        }),
+        test_rom("delay-countdown", |p| {
+            // Spins until the delay timer reaches zero
+            p.op(0x6002).op(0xF015);
+            let poll = p.here();
+            p.op(0xF107).op(0x3100).op(0x1000 | poll);
+        }),
+        test_rom("ld-f", |p| {
+            p.op(0x6001).op(0xF029).op(0xF065).expect(0, 0x20);
+        }),
+        test_rom("ld-b", |p| {
+            p.op(0x60FF).op(0xA300).op(0xF033).op(0xF265).expect(0, 2).expect(1, 5).expect(2, 5);
+            p.op(0x6009).op(0xF033).op(0xF265).expect(0, 0).expect(1, 0).expect(2, 9);
+        }),
+        test_rom("ld-mem", |p| {
+            for register in 0..16 {
+                p.op(0x6000 | register << 8 | (register + 1));
+            }
+            p.op(0xA300).op(0xFF55);
+            for register in 0..16 {
+                p.op(0x6000 | register << 8);
+            }
+            p.op(0xA300).op(0xFF65);
+            for register in 0..16 {
+                p.expect(register as u8, register as u8 + 1);
+            }
+        }),
+    )
+}
+
+/// The verdict currently shown on a 64x32 display.
+pub fn verdict(display: &[u8]) -> Verdict {
+    let glyph = |sprite: &[u8; 5]| {
+        let mut expected = [0u8; 64 * 32];
+        for (row, bits) in sprite.iter().enumerate() {
+            for column in 0..8 {
+                expected[(GLYPH_Y + row) * 64 + GLYPH_X + column] = (bits >> (7 - column)) & 1;
+            }
+        }
+        expected
+    };
+
+    if display == &glyph(&PASS_SPRITE)[..] {
+        Verdict::Pass
+    } else if display == &glyph(&FAIL_SPRITE)[..] {
+        Verdict::Fail
+    } else {
+        Verdict::Unknown
+    }
+}
+
+/// Runs a test ROM on this emulator until it draws a verdict or `cycles` have passed.
+pub fn run(rom: &[u8], cycles: u64) -> Verdict {
+    let mut chip8 = match Chip8::builder().program(rom.to_vec()).clock(Clock::Cycles(20)).seed(0).build() {
+        Ok(chip8) => chip8,
+        Err(_) => return Verdict::Unknown,
+    };
+
+    let keyboard = HashMap::new();
+
+    for _ in 0..cycles {
+        match chip8.step(&keyboard, None) {
+            Ok(state) => if state.update_display {
+                if let found @ (Verdict::Pass | Verdict::Fail) = verdict(&state.display) {
+                    return found;
+                }
+            },
+            Err(_) => return Verdict::Unknown,
+        }
+    }
+
+    Verdict::Unknown
+}
+
+#[test]
+fn test_all_roms_pass() {
+    for test in test_roms() {
+        assert_eq!(Verdict::Pass, run(&test.rom, 10_000), "{}", test.name);
+    }
+}
+
+#[test]
+fn test_failing_check() {
+    let mut program = Program::new();
+    program.op(0x6000).expect(0, 1).pass();
+
+    assert_eq!(Verdict::Fail, run(&program.to_bytes(), 10_000));
+    assert_eq!(Verdict::Unknown, run(&Program::new().to_bytes()[..2], 100));
+}
@@ -0,0 +1,172 @@
+use crate::chip8::Chip8;
+use crate::instruction::{Instruction, OpCode};
+
+use std::collections::VecDeque;
+#[cfg(test)]
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+enum TraceOutput {
+    File(BufWriter<File>),
+    Ring { lines: VecDeque<String>, capacity: usize },
+}
+
+/// Records every executed instruction as one line of text, with the machine
+/// state as it is after the instruction has run.
+pub struct Tracer {
+    output: TraceOutput,
+}
+
+impl Tracer {
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Tracer> {
+        let file = File::create(path)?;
+        Ok(Tracer { output: TraceOutput::File(BufWriter::new(file)) })
+    }
+
+    /// Keeps only the last `capacity` lines in memory. A capacity of zero is
+    /// treated as one, so the ring never grows without bound.
+    pub fn ring(capacity: usize) -> Tracer {
+        let capacity = capacity.max(1);
+        Tracer { output: TraceOutput::Ring { lines: VecDeque::with_capacity(capacity), capacity } }
+    }
+
+    pub fn record(&mut self, chip8: &Chip8) -> io::Result<()> {
+        let (pc, word) = match chip8.last_executed() {
+            Some(executed) => executed,
+            None => return Ok(()),
+        };
+
+        let line = format_line(chip8, pc, word);
+
+        match &mut self.output {
+            TraceOutput::File(writer) => writeln!(writer, "{}", line),
+            TraceOutput::Ring { lines, capacity } => {
+                if lines.len() >= *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+                Ok(())
+            },
+        }
+    }
+
+    pub fn lines(&self) -> Vec<&str> {
+        match &self.output {
+            TraceOutput::File(_) => vec!(),
+            TraceOutput::Ring { lines, .. } => lines.iter().map(|line| line.as_str()).collect(),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.output {
+            TraceOutput::File(writer) => writer.flush(),
+            TraceOutput::Ring { .. } => Ok(()),
+        }
+    }
+}
+
+pub fn format_line(chip8: &Chip8, pc: u16, word: u16) -> String {
+    let opcode: OpCode = Instruction::from(word).into();
+    let registers: Vec<String> = chip8.v.iter().map(|v| format!("{:02X}", v)).collect();
+
+    format!("{:04X} {:04X} {:<16} V:{} I:{:04X} SP:{:02X} DT:{:02X} ST:{:02X}",
+        pc, word, opcode.to_string(), registers.join(" "), chip8.i, chip8.stack.len(), chip8.delay_timer, chip8.sound_timer)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub line: usize,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+/// Compares two traces line by line and returns the first line where they differ.
+pub fn first_divergence<A: BufRead, B: BufRead>(left: A, right: B) -> io::Result<Option<Divergence>> {
+    let mut left = left.lines();
+    let mut right = right.lines();
+    let mut line = 0;
+
+    loop {
+        line += 1;
+
+        let (l, r) = match (left.next().transpose()?, right.next().transpose()?) {
+            (None, None) => return Ok(None),
+            (l, r) => (l, r),
+        };
+
+        if l != r {
+            return Ok(Some(Divergence { line, left: l, right: r }));
+        }
+    }
+}
+
+#[test]
+fn test_format_line() {
+    let mut chip8 = Chip8::new_program(vec!(0x6A, 0x2F));
+    chip8.step(&HashMap::new(), None).unwrap();
+
+    let (pc, word) = chip8.last_executed().unwrap();
+    assert_eq!(
+        "0200 6A2F LD VA, 0x2F      V:00 00 00 00 00 00 00 00 00 00 2F 00 00 00 00 00 I:0000 SP:00 DT:00 ST:00",
+        format_line(&chip8, pc, word));
+}
+
+#[test]
+fn test_ring_is_bounded() {
+    // LD V0, 0x01; ADD V0, 0x01; JP 0x202
+    let mut chip8 = Chip8::new_program(vec!(0x60, 0x01, 0x70, 0x01, 0x12, 0x02));
+    let mut tracer = Tracer::ring(3);
+
+    for _ in 0..9 {
+        chip8.step(&HashMap::new(), None).unwrap();
+        tracer.record(&chip8).unwrap();
+    }
+
+    let lines = tracer.lines();
+    assert_eq!(3, lines.len());
+    assert!(lines[2].starts_with("0204 1202 JP 0x202"));
+}
+
+#[test]
+fn test_ring_of_zero_keeps_last_line() {
+    // LD V0, 0x01; ADD V0, 0x01
+    let mut chip8 = Chip8::new_program(vec!(0x60, 0x01, 0x70, 0x01));
+    let mut tracer = Tracer::ring(0);
+
+    for _ in 0..2 {
+        chip8.step(&HashMap::new(), None).unwrap();
+        tracer.record(&chip8).unwrap();
+    }
+
+    let lines = tracer.lines();
+    assert_eq!(1, lines.len());
+    assert!(lines[0].starts_with("0202 7001 ADD V0, 0x01"));
+}
+
+#[test]
+fn test_faulting_instruction_is_traced() {
+    // RET with an empty stack
+    let mut chip8 = Chip8::new_program(vec!(0x00, 0xEE));
+    let mut tracer = Tracer::ring(4);
+
+    assert!(chip8.step(&HashMap::new(), None).is_err());
+    tracer.record(&chip8).unwrap();
+
+    assert!(tracer.lines()[0].starts_with("0200 00EE RET"));
+}
+
+#[test]
+fn test_first_divergence() {
+    let left = "a\nb\nc\n";
+
+    assert_eq!(None, first_divergence(left.as_bytes(), "a\nb\nc\n".as_bytes()).unwrap());
+    assert_eq!(
+        Some(Divergence { line: 2, left: Some("b".to_string()), right: Some("x".to_string()) }),
+        first_divergence(left.as_bytes(), "a\nx\nc\n".as_bytes()).unwrap());
+    assert_eq!(
+        Some(Divergence { line: 3, left: Some("c".to_string()), right: None }),
+        first_divergence(left.as_bytes(), "a\nb\n".as_bytes()).unwrap());
+}
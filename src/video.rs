@@ -0,0 +1,87 @@
+//! Lossless video export as a YUV4MPEG2 (Y4M) stream with one picture per
+//! emulated frame, at 60 frames a second.
+
+use crate::clip::{self, Palette, HEIGHT, WIDTH};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    scale: usize,
+    // Y, Cb and Cr of the background and foreground
+    colors: [[u8; 3]; 2],
+    frames: u64,
+}
+
+impl Y4mWriter<BufWriter<File>> {
+    pub fn create(filename: &str, palette: Palette, scale: usize) -> io::Result<Self> {
+        Y4mWriter::new(BufWriter::new(File::create(filename)?), palette, scale)
+    }
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut writer: W, palette: Palette, scale: usize) -> io::Result<Self> {
+        let scale = scale.max(1);
+        // 4:4:4 so single pixels keep their color
+        writeln!(writer, "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444", WIDTH * scale, HEIGHT * scale)?;
+
+        Ok(Y4mWriter { writer, scale, colors: [ycbcr(palette.background), ycbcr(palette.foreground)], frames: 0 })
+    }
+
+    /// Writes the display as shown for one emulated frame.
+    pub fn write_frame(&mut self, display: &[u8]) -> io::Result<()> {
+        let pixels = clip::scale_image(display, self.scale);
+
+        self.writer.write_all(b"FRAME\n")?;
+        for plane in 0..3 {
+            let samples: Vec<u8> = pixels.iter().map(|pixel| self.colors[*pixel as usize][plane]).collect();
+            self.writer.write_all(&samples)?;
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// BT.601 in the limited range most players expect
+fn ycbcr([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let cb = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let cr = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+
+    [y.round() as u8, cb.round() as u8, cr.round() as u8]
+}
+
+#[test]
+fn test_write_frame() {
+    let mut writer = Y4mWriter::new(vec!(), Palette::default(), 1).unwrap();
+    let mut display = [0; WIDTH * HEIGHT];
+    display[1] = 1;
+
+    writer.write_frame(&display).unwrap();
+    writer.write_frame(&[0; WIDTH * HEIGHT]).unwrap();
+    assert_eq!(2, writer.frames());
+
+    let output = writer.into_inner().unwrap();
+    let header = b"YUV4MPEG2 W64 H32 F60:1 Ip A1:1 C444\n";
+    assert_eq!(&header[..], &output[..header.len()]);
+
+    let frame_size = b"FRAME\n".len() + 3 * WIDTH * HEIGHT;
+    assert_eq!(header.len() + 2 * frame_size, output.len());
+
+    let luma = &output[header.len() + 6..];
+    assert_eq!(&[16, 235, 16], &luma[..3]);
+    assert_eq!([235, 128, 128], ycbcr([255, 255, 255]));
+}
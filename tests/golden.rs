@@ -0,0 +1,154 @@
+//! Golden-image regression tests. Every ROM in `tests/roms` is run headless for
+//! the number of cycles given in its `.manifest`, and the final display is
+//! compared with its `.golden` file. Run with `-- --bless` to rewrite the
+//! golden files after an intended change.
+
+use chip8::{ApplicationState, KeyboardHandler, StateHandler};
+use chip8::chip8::{Chip8, Clock};
+use chip8::headless::HeadlessEngine;
+use chip8::quirks::Quirks;
+use chip8::script::ScriptedKeyboard;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const WIDTH: usize = 64;
+
+struct Manifest {
+    cycles: u64,
+    quirks: Quirks,
+    script: Option<String>,
+}
+
+fn parse_manifest(text: &str) -> Result<Manifest, String> {
+    let mut manifest = Manifest { cycles: 1000, quirks: Quirks::default(), script: None };
+
+    for line in text.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let mut parts = line.splitn(2, '=').map(|part| part.trim());
+
+        match (parts.next(), parts.next()) {
+            (Some("cycles"), Some(value)) => manifest.cycles = value.parse().map_err(|_| format!("invalid cycles {}", value))?,
+            (Some("quirks"), Some(value)) => manifest.quirks = Quirks::parse(value)?,
+            (Some("script"), Some(value)) => manifest.script = Some(value.to_string()),
+            _ => return Err(format!("invalid manifest line: {}", line)),
+        }
+    }
+
+    Ok(manifest)
+}
+
+fn run<E: KeyboardHandler + StateHandler>(chip8: &mut Chip8, engine: &mut E) -> Result<(), String> {
+    loop {
+        let (keyboard, keydown, application_state) = engine.handle_keyboard();
+        if let ApplicationState::Stopping = application_state {
+            return Ok(());
+        }
+
+        let state = chip8.step(keyboard, keydown).map_err(|fault| fault.to_string())?;
+        engine.handle_state(state);
+    }
+}
+
+fn render(rom: &Path) -> Result<String, String> {
+    let program = fs::read(rom).map_err(|e| e.to_string())?;
+    let manifest = fs::read_to_string(rom.with_extension("manifest")).map_err(|e| format!("manifest: {}", e))?;
+    let manifest = parse_manifest(&manifest)?;
+
+    let mut chip8 = Chip8::builder()
+        .program(program)
+        .quirks(manifest.quirks)
+        .clock(Clock::Cycles(20))
+        .seed(0)
+        .build()?;
+
+    let engine = HeadlessEngine::new(Some(manifest.cycles));
+    let engine = match manifest.script {
+        Some(script) => {
+            let mut engine = ScriptedKeyboard::new(engine, &script)?;
+            run(&mut chip8, &mut engine)?;
+            engine.into_inner()
+        },
+        None => {
+            let mut engine = engine;
+            run(&mut chip8, &mut engine)?;
+            engine
+        },
+    };
+
+    Ok(engine.display()
+        .chunks(WIDTH)
+        .map(|row| row.iter().map(|pixel| if *pixel > 0 { '#' } else { '.' }).collect::<String>() + "\n")
+        .collect())
+}
+
+/// Marks pixels that differ: `-` should be lit but is not, `+` is lit but should not be.
+fn diff(expected: &str, actual: &str) -> String {
+    expected.lines()
+        .zip(actual.lines())
+        .map(|(expected, actual)| {
+            expected.chars()
+                .zip(actual.chars())
+                .map(|(expected, actual)| match (expected, actual) {
+                    ('#', '.') => '-',
+                    ('.', '#') => '+',
+                    (_, actual) => actual,
+                })
+                .collect::<String>() + "\n"
+        })
+        .collect()
+}
+
+fn roms() -> Vec<PathBuf> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms"))
+        .expect("tests/roms is missing")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map(|extension| extension == "ch8").unwrap_or(false))
+        .collect();
+
+    roms.sort();
+    roms
+}
+
+fn main() {
+    let bless = std::env::args().any(|arg| arg == "--bless");
+    let mut failed = 0;
+
+    for rom in roms() {
+        let name = rom.file_stem().unwrap().to_string_lossy().to_string();
+        let golden = rom.with_extension("golden");
+
+        let actual = match render(&rom) {
+            Ok(actual) => actual,
+            Err(e) => {
+                println!("{} ... error: {}", name, e);
+                failed += 1;
+                continue;
+            },
+        };
+
+        if bless {
+            fs::write(&golden, &actual).expect("could not write golden image");
+            println!("{} ... blessed", name);
+            continue;
+        }
+
+        match fs::read_to_string(&golden) {
+            Ok(ref expected) if *expected == actual => println!("{} ... ok", name),
+            Ok(expected) => {
+                println!("{} ... FAILED, display differs from {}:", name, golden.display());
+                print!("{}", diff(&expected, &actual));
+                failed += 1;
+            },
+            Err(e) => {
+                println!("{} ... error: {}: {}", name, golden.display(), e);
+                failed += 1;
+            },
+        }
+    }
+
+    if failed > 0 {
+        println!("{} golden image(s) failed; rerun with `cargo test --test golden -- --bless` if the change is intended", failed);
+        process::exit(1);
+    }
+}